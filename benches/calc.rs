@@ -0,0 +1,64 @@
+//! Compare the tree walking evaluator with the compiled stack machine
+//! Run with `cargo bench`
+use interview::{compile, eval_with, parse, Environment, Value};
+use std::time::Instant;
+
+const RUNS: usize = 1_000_000;
+/// number of different values given to `hours`
+const INPUTS: usize = 100;
+
+fn main() {
+    let mut env = Environment::new();
+    env.set("hours", 0.0);
+    env.set("rate", 12.5);
+    for txt in [
+        "rate * hours",
+        "(rate * hours - 3) * 1.2 + max(hours - 35, 0) * rate * 0.5",
+        "sqrt(hours^2 + rate^2) / (1 + -hours % 7) - cos(hours) * sin(rate)",
+    ]
+    .iter()
+    {
+        let n = parse(txt).unwrap();
+        let p = compile(&n, &env).unwrap();
+        // the inputs are set up before timing, so both loops only pay for the evaluation
+        let envs: Vec<Environment> = (0..INPUTS)
+            .map(|i| {
+                let mut e = env.clone();
+                e.set("hours", i as f64);
+                e
+            })
+            .collect();
+        let values: Vec<Vec<Value>> = envs
+            .iter()
+            .map(|e| p.variables().iter().map(|v| e.get(v).unwrap()).collect())
+            .collect();
+
+        let start = Instant::now();
+        let mut total = 0.0;
+        for i in 0..RUNS {
+            total += eval_with(&n, &envs[i % INPUTS])
+                .and_then(Value::number)
+                .unwrap();
+        }
+        let tree = start.elapsed();
+
+        let start = Instant::now();
+        let mut total2 = 0.0;
+        for i in 0..RUNS {
+            total2 += p
+                .run_slots(&values[i % INPUTS])
+                .and_then(Value::number)
+                .unwrap();
+        }
+        let vm = start.elapsed();
+
+        assert_eq!(total, total2);
+        println!("{}", txt);
+        println!(
+            "  tree walk: {:?}, stack machine: {:?} ({:.1}x)",
+            tree,
+            vm,
+            tree.as_secs_f64() / vm.as_secs_f64()
+        );
+    }
+}
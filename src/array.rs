@@ -197,7 +197,7 @@ fn partition(nbs: &mut [i32], low: usize, high: usize) -> usize {
 }
 
 /// bubble sort
-pub fn bubble_sort(nbs: &mut Vec<i32>) {
+pub fn bubble_sort(nbs: &mut [i32]) {
     loop {
         let mut changed = false;
         for i in 0..nbs.len() - 1 {
@@ -213,7 +213,7 @@ pub fn bubble_sort(nbs: &mut Vec<i32>) {
 }
 
 /// insertion sort
-pub fn insertion_sort(nbs: &mut Vec<i32>) {
+pub fn insertion_sort(nbs: &mut [i32]) {
     for i in 1..nbs.len() {
         let mut j = i;
         while j > 0 && nbs[j - 1] > nbs[j] {
@@ -255,7 +255,7 @@ fn merge_sort_step(nbs: &Vec<i32>, low: usize, high: usize) -> Vec<i32> {
 }
 
 /// heap sort
-pub fn heap_sort(nbs: &mut Vec<i32>) {
+pub fn heap_sort(nbs: &mut [i32]) {
     heapify(nbs);
     let mut end = nbs.len() - 1;
     while end > 0 {
@@ -266,7 +266,7 @@ pub fn heap_sort(nbs: &mut Vec<i32>) {
 }
 
 /// put elements of the vec in heap order
-fn heapify(nbs: &mut Vec<i32>) {
+fn heapify(nbs: &mut [i32]) {
     let mut end = 1;
     let c = nbs.len();
     while end < c {
@@ -327,7 +327,7 @@ fn heap_right(i: usize) -> usize {
 }
 
 /// reverse in place
-pub fn reverse(nbs: &mut Vec<i32>) {
+pub fn reverse(nbs: &mut [i32]) {
     if !nbs.is_empty() {
         let mut i = 0;
         let mut j = nbs.len() - 1;
@@ -552,12 +552,12 @@ mod tests {
 
     #[test]
     fn test_bubble_sort() {
-        test_sort(&bubble_sort);
+        test_sort(&|nbs| bubble_sort(nbs));
     }
 
     #[test]
     fn test_insertion_sort() {
-        test_sort(&insertion_sort);
+        test_sort(&|nbs| insertion_sort(nbs));
     }
 
     #[test]
@@ -567,7 +567,7 @@ mod tests {
 
     #[test]
     fn test_heap_sort() {
-        test_sort(&heap_sort);
+        test_sort(&|nbs| heap_sort(nbs));
     }
 
     fn test_sort(f: &dyn Fn(&mut Vec<i32>)) {
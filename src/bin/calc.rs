@@ -0,0 +1,233 @@
+//! Interactive calculator: type an expression to evaluate it, `:help` to list the commands
+use interview::{eval_with, parse, parse_statement, CalcError, Environment, Statement, Value};
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = "> ";
+
+const HELP: &str = "\
+<expression>          evaluate the expression, the result is kept as _1, _2...
+let <name> = <expr>   evaluate the expression and bind it to the name
+<f>(<x>...) = <expr>  define a function of the given parameters
+:ast <expression>     show the parsed tree of the expression
+:help                 show this help
+:quit                 exit (or end of input)";
+
+/// An error, with the column it was found at if known
+#[derive(Debug, PartialEq)]
+struct ReplError {
+    column: Option<usize>,
+    message: String,
+}
+
+impl ReplError {
+    /// wrap a calculator error, the expression starting at the given column of the line
+    fn new(e: CalcError, start: usize) -> ReplError {
+        let column = match &e {
+            CalcError::Parse(pe) => Some(start + pe.pos),
+            CalcError::Eval(_) => None,
+        };
+        ReplError {
+            column,
+            message: e.to_string(),
+        }
+    }
+}
+
+/// The state of the session
+struct Repl {
+    env: Environment,
+    /// number of results in the history
+    results: usize,
+}
+
+impl Repl {
+    fn new() -> Repl {
+        Repl {
+            env: Environment::new(),
+            results: 0,
+        }
+    }
+
+    /// process one line of input, returning what to print
+    fn eval_line(&mut self, line: &str) -> Result<String, ReplError> {
+        let trimmed = line.trim();
+        let start = line.chars().count() - line.trim_start().chars().count();
+        if trimmed.is_empty() {
+            Ok(String::new())
+        } else if trimmed == ":help" {
+            Ok(HELP.to_string())
+        } else if let Some(expr) = trimmed
+            .strip_prefix(":ast ")
+            .or_else(|| trimmed.strip_prefix(":ast").filter(|e| e.is_empty()))
+        {
+            let offset = start + trimmed.len() - expr.len();
+            let node = parse(expr).map_err(|e| ReplError::new(e.into(), offset))?;
+            Ok(format!("{:#?}", node))
+        } else if let Some(rest) = trimmed.strip_prefix("let ") {
+            let eq = rest.find('=').ok_or_else(|| ReplError {
+                column: None,
+                message: String::from("expected let <name> = <expression>"),
+            })?;
+            let name = rest[..eq].trim();
+            if !is_identifier(name) {
+                return Err(ReplError {
+                    column: None,
+                    message: format!("'{}' is not a valid name", name),
+                });
+            }
+            let expr = &rest[eq + 1..];
+            let v = self.eval(expr, start + 4 + rest[..=eq].chars().count())?;
+            self.env.set(name, v.clone());
+            Ok(format!("{} = {}", name, v))
+        } else if trimmed.starts_with(':') {
+            Err(ReplError {
+                column: None,
+                message: format!("unknown command {}, try :help", trimmed),
+            })
+        } else {
+            let node = match parse_statement(trimmed) {
+                Ok(Statement::Definition(d)) => {
+                    let out = d.to_string();
+                    self.env.define(d);
+                    return Ok(out);
+                }
+                Ok(Statement::Expression(node)) => node,
+                Err(e) => return Err(ReplError::new(e.into(), start)),
+            };
+            let v = eval_with(&node, &self.env).map_err(|e| ReplError::new(e.into(), start))?;
+            self.results += 1;
+            let name = format!("_{}", self.results);
+            self.env.set(&name, v.clone());
+            Ok(format!("{} = {}", name, v))
+        }
+    }
+
+    /// evaluate an expression starting at the given column
+    fn eval(&self, expr: &str, start: usize) -> Result<Value, ReplError> {
+        let node = parse(expr).map_err(|e| ReplError::new(e.into(), start))?;
+        eval_with(&node, &self.env).map_err(|e| ReplError::new(e.into(), start))
+    }
+}
+
+/// is the name a valid variable name
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut repl = Repl::new();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{}", PROMPT);
+        io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        if line.trim() == ":quit" {
+            break;
+        }
+        match repl.eval_line(&line) {
+            Ok(out) if out.is_empty() => (),
+            Ok(out) => println!("{}", out),
+            Err(e) => {
+                if let Some(column) = e.column {
+                    println!("{}^", " ".repeat(PROMPT.len() + column));
+                }
+                println!("error: {}", e.message);
+            }
+        }
+    }
+    println!();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repl() {
+        let mut repl = Repl::new();
+        assert_eq!(Ok(String::from("_1 = 4")), repl.eval_line("2+2"));
+        assert_eq!(Ok(String::from("x = 6")), repl.eval_line("let x = _1 + 2"));
+        assert_eq!(Ok(String::from("_2 = 24")), repl.eval_line("  _1 * x"));
+        assert_eq!(Ok(String::new()), repl.eval_line("   "));
+        assert_eq!(Ok(String::from("x = 1")), repl.eval_line("let x=1"));
+        assert!(repl.eval_line(":ast 1+x").unwrap().contains("Variable"));
+        assert_eq!(
+            Ok(String::from("_3 = true")),
+            repl.eval_line("x < 2 && _2 > 0")
+        );
+        assert_eq!(Ok(String::from("b = false")), repl.eval_line("let b = !_3"));
+        assert_eq!(Ok(String::from("_4 = 2")), repl.eval_line("if(b, 1, 2)"));
+        assert!(repl.eval_line(":help").unwrap().contains("let <name>"));
+        assert_eq!(
+            Ok(String::from("f(x, y) = x ^ 2 + y")),
+            repl.eval_line("f(x, y) = x^2 + y")
+        );
+        assert_eq!(Ok(String::from("_5 = 13")), repl.eval_line("f(3, 4)"));
+        assert_eq!(
+            Ok(String::from(
+                "fib(n) = if(n < 2, n, fib(n - 1) + fib(n - 2))"
+            )),
+            repl.eval_line("fib(n) = if(n < 2, n, fib(n-1) + fib(n-2))")
+        );
+        assert_eq!(Ok(String::from("_6 = 55")), repl.eval_line("fib(10)"));
+    }
+
+    #[test]
+    fn test_repl_errors() {
+        let mut repl = Repl::new();
+        assert_eq!(Some(3), repl.eval_line("2+2)").unwrap_err().column);
+        assert_eq!(Some(5), repl.eval_line("  2+2)").unwrap_err().column);
+        assert_eq!(Some(11), repl.eval_line("let y = 2+2)").unwrap_err().column);
+        assert_eq!(Some(8), repl.eval_line(":ast 2+2)").unwrap_err().column);
+        assert_eq!(
+            Err(ReplError {
+                column: None,
+                message: String::from("unbound variable 'y'")
+            }),
+            repl.eval_line("y")
+        );
+        assert_eq!(
+            Err(ReplError {
+                column: None,
+                message: String::from("'2y' is not a valid name")
+            }),
+            repl.eval_line("let 2y = 3")
+        );
+        assert_eq!(
+            Err(ReplError {
+                column: None,
+                message: String::from("expected a boolean, found a number")
+            }),
+            repl.eval_line("if(1, 2, 3)")
+        );
+        assert_eq!(None, repl.eval_line(":foo").unwrap_err().column);
+        assert_eq!(
+            Err(ReplError {
+                column: None,
+                message: String::from("unknown command :astfoo, try :help")
+            }),
+            repl.eval_line(":astfoo")
+        );
+        assert_eq!(Some(4), repl.eval_line(":ast").unwrap_err().column);
+        assert_eq!(Some(4), repl.eval_line("f(x 1) = 2").unwrap_err().column);
+        repl.eval_line("down(n) = down(n + 1)").unwrap();
+        assert_eq!(
+            Err(ReplError {
+                column: None,
+                message: String::from("function 'down' exceeded the limit of 100 nested calls")
+            }),
+            repl.eval_line("down(0)")
+        );
+        // failed evaluations are not part of the history
+        assert_eq!(Ok(String::from("_1 = 1")), repl.eval_line("1"));
+    }
+}
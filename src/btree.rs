@@ -2,11 +2,23 @@
 use std::cmp::max;
 use std::iter::FromIterator;
 use std::iter::IntoIterator;
+use std::iter::Peekable;
+use std::ops::{Bound, RangeBounds};
+
+pub mod level_order;
+pub mod persistent;
+pub mod render;
+
+pub use self::level_order::*;
+pub use self::persistent::*;
+pub use self::render::*;
 
 /// The Binary Search Tree, possibly empty
 #[derive(Debug)]
 pub struct BTree<K, V> {
     root: Option<Box<BNode<K, V>>>,
+    /// rebalance as an AVL tree on insertion and deletion, keeping the depth in O(log n)
+    balanced: bool,
 }
 
 impl<K: Eq + PartialOrd, V> Default for BTree<K, V> {
@@ -15,6 +27,9 @@ impl<K: Eq + PartialOrd, V> Default for BTree<K, V> {
     }
 }
 
+/// A possibly empty subtree
+type Link<K, V> = Option<Box<BNode<K, V>>>;
+
 /// A node in the tree
 #[derive(Debug)]
 struct BNode<K, V> {
@@ -22,19 +37,64 @@ struct BNode<K, V> {
     value: V,
     left: Option<Box<BNode<K, V>>>,
     right: Option<Box<BNode<K, V>>>,
+    /// depth of the subtree rooted at this node
+    height: usize,
+    /// number of nodes in the subtree rooted at this node
+    size: usize,
+}
+
+impl<K, V> BNode<K, V> {
+    /// new node without children
+    fn leaf(key: K, value: V) -> BNode<K, V> {
+        BNode {
+            key,
+            value,
+            left: None,
+            right: None,
+            height: 1,
+            size: 1,
+        }
+    }
+
+    /// recompute the height and the size from the children
+    fn update(&mut self) {
+        self.height = max(height(&self.left), height(&self.right)) + 1;
+        self.size = size(&self.left) + size(&self.right) + 1;
+    }
+
+    /// height of the left subtree minus height of the right subtree
+    fn balance(&self) -> isize {
+        height(&self.left) as isize - height(&self.right) as isize
+    }
 }
 
 impl<'a, K: Eq + PartialOrd, V> BTree<K, V> {
-    /// new empty tree
+    /// new empty tree, that does not rebalance: its depth depends on the insertion order
     pub fn new() -> BTree<K, V> {
-        BTree { root: None }
+        BTree {
+            root: None,
+            balanced: false,
+        }
+    }
+
+    /// new empty self-balancing (AVL) tree
+    pub fn new_balanced() -> BTree<K, V> {
+        BTree {
+            root: None,
+            balanced: true,
+        }
+    }
+
+    /// is the tree self-balancing
+    pub fn is_balanced(&self) -> bool {
+        self.balanced
     }
 
     /// insert key/value
     pub fn insert(&mut self, key: K, value: V) {
         let oroot = self.root.take();
-        let newroot = insert_into_node(oroot, key, value);
-        self.root = Some(Box::new(newroot));
+        let (newroot, _) = insert_into_node(oroot, key, value, self.balanced);
+        self.root = Some(newroot);
     }
 
     /// is the tree empty
@@ -43,26 +103,264 @@ impl<'a, K: Eq + PartialOrd, V> BTree<K, V> {
     }
 
     /// get a reference to a value for the given key, if present
-    pub fn get(&'a self, key: &K) -> Option<&V> {
+    pub fn get(&'a self, key: &K) -> Option<&'a V> {
         search_node(&self.root, key)
     }
 
+    /// get a mutable reference to a value for the given key, if present
+    pub fn get_mut(&'a mut self, key: &K) -> Option<&'a mut V> {
+        search_node_mut(&mut self.root, key).map(|node| &mut node.value)
+    }
+
+    /// in-order iterator giving mutable references to the values
+    pub fn iter_mut(&'a mut self) -> BTIterMut<'a, K, V> {
+        let mut it = BTIterMut { stack: vec![] };
+        it.push_left(&mut self.root);
+        it
+    }
+
+    /// the entry for the given key, to update or insert its value in place
+    pub fn entry(&'a mut self, key: K) -> Entry<'a, K, V> {
+        // compare the keys on a shared walk recording the way down, then follow it without
+        // comparing again: a mutable walk ending at an empty slot would keep self borrowed
+        let mut way = vec![];
+        let mut on = self.root.as_deref();
+        while let Some(node) = on {
+            if node.key == key {
+                break;
+            }
+            let right = key >= node.key;
+            way.push(right);
+            on = if right {
+                node.right.as_deref()
+            } else {
+                node.left.as_deref()
+            };
+        }
+        let found = on.is_some();
+        let path: Path = way.into_iter().rev().collect();
+        if found {
+            let root = self.root.as_deref_mut().expect("the key was found");
+            let node = follow(root, path);
+            Entry::Occupied(OccupiedEntry {
+                key: &node.key,
+                value: &mut node.value,
+            })
+        } else {
+            Entry::Vacant(VacantEntry {
+                key,
+                tree: self,
+                path,
+            })
+        }
+    }
+
     /// delete a key and return the value if it was present
     pub fn delete(&'a mut self, key: &K) -> Option<V> {
         let r = self.root.take();
-        let (r, v) = delete_node(r, key);
+        let (r, v) = delete_node(r, key, self.balanced);
         self.root = r;
         v
     }
 
     /// number of elements in the tree
     pub fn len(&self) -> usize {
-        self.into_iter().count()
+        size(&self.root)
+    }
+
+    /// number of keys less than the given key
+    pub fn rank(&self, key: &K) -> usize {
+        let mut rank = 0;
+        let mut on = &self.root;
+        while let Some(node) = on {
+            if node.key < *key {
+                rank += size(&node.left) + 1;
+                on = &node.right;
+            } else {
+                on = &node.left;
+            }
+        }
+        rank
+    }
+
+    /// the entry with the given index in key order, starting from 0
+    pub fn select(&'a self, index: usize) -> Option<(&'a K, &'a V)> {
+        let mut index = index;
+        let mut on = &self.root;
+        while let Some(node) = on {
+            let left = size(&node.left);
+            if index < left {
+                on = &node.left;
+            } else if index == left {
+                return Some((&node.key, &node.value));
+            } else {
+                index -= left + 1;
+                on = &node.right;
+            }
+        }
+        None
     }
 
     /// depth of the tree
     pub fn depth(&self) -> usize {
-        depth(&self.root)
+        height(&self.root)
+    }
+
+    /// in-order iterator
+    pub fn iter(&'a self) -> BTIterator<'a, K, V> {
+        self.into_iter()
+    }
+
+    /// in-order iterator over the keys
+    pub fn keys(&'a self) -> BTKeys<'a, K, V> {
+        BTKeys(self.iter())
+    }
+
+    /// in-order iterator over the values
+    pub fn values(&'a self) -> BTValues<'a, K, V> {
+        BTValues(self.iter())
+    }
+
+    /// in-order iterator over a range of keys, skipping the subtrees outside of the range
+    pub fn range<R: RangeBounds<K>>(&'a self, range: R) -> BTIterator<'a, K, V> {
+        let mut iter = BTIterator {
+            stack: vec![],
+            back: vec![],
+            remaining: 0,
+        };
+        // count the keys before the start and after the end on the way down
+        let mut before = 0;
+        let mut on = &self.root;
+        while let Some(n) = on {
+            let after_start = match range.start_bound() {
+                Bound::Included(s) => n.key >= *s,
+                Bound::Excluded(s) => n.key > *s,
+                Bound::Unbounded => true,
+            };
+            if after_start {
+                iter.stack.push(n);
+                on = &n.left;
+            } else {
+                before += size(&n.left) + 1;
+                on = &n.right;
+            }
+        }
+        let mut after = 0;
+        let mut on = &self.root;
+        while let Some(n) = on {
+            let before_end = match range.end_bound() {
+                Bound::Included(e) => n.key <= *e,
+                Bound::Excluded(e) => n.key < *e,
+                Bound::Unbounded => true,
+            };
+            if before_end {
+                iter.back.push(n);
+                on = &n.right;
+            } else {
+                after += size(&n.right) + 1;
+                on = &n.left;
+            }
+        }
+        iter.remaining = self.len().saturating_sub(before + after);
+        iter
+    }
+
+    /// the entry with the smallest key
+    pub fn first(&'a self) -> Option<(&'a K, &'a V)> {
+        let mut on = self.root.as_ref()?;
+        while let Some(l) = &on.left {
+            on = l;
+        }
+        Some((&on.key, &on.value))
+    }
+
+    /// the entry with the largest key
+    pub fn last(&'a self) -> Option<(&'a K, &'a V)> {
+        let mut on = self.root.as_ref()?;
+        while let Some(r) = &on.right {
+            on = r;
+        }
+        Some((&on.key, &on.value))
+    }
+
+    /// the entry with the greatest key less than or equal to the given key
+    pub fn floor(&'a self, key: &K) -> Option<(&'a K, &'a V)> {
+        below(&self.root, key, true)
+    }
+
+    /// the entry with the least key greater than or equal to the given key
+    pub fn ceiling(&'a self, key: &K) -> Option<(&'a K, &'a V)> {
+        above(&self.root, key, true)
+    }
+
+    /// the entry with the least key strictly greater than the given key
+    pub fn successor(&'a self, key: &K) -> Option<(&'a K, &'a V)> {
+        above(&self.root, key, false)
+    }
+
+    /// the entry with the greatest key strictly less than the given key
+    pub fn predecessor(&'a self, key: &K) -> Option<(&'a K, &'a V)> {
+        below(&self.root, key, false)
+    }
+
+    /// lazy in-order iterator over the entries in either tree, with the values of this one for the common keys
+    pub fn union(&'a self, other: &'a BTree<K, V>) -> BTSetOp<'a, K, V> {
+        BTSetOp::new(self, other, SetOp::Union)
+    }
+
+    /// lazy in-order iterator over the entries of this tree whose key is also in the other one
+    pub fn intersection(&'a self, other: &'a BTree<K, V>) -> BTSetOp<'a, K, V> {
+        BTSetOp::new(self, other, SetOp::Intersection)
+    }
+
+    /// lazy in-order iterator over the entries of this tree whose key is not in the other one
+    pub fn difference(&'a self, other: &'a BTree<K, V>) -> BTSetOp<'a, K, V> {
+        BTSetOp::new(self, other, SetOp::Difference)
+    }
+
+    /// lazy in-order iterator over the entries whose key is in exactly one of the trees
+    pub fn symmetric_difference(&'a self, other: &'a BTree<K, V>) -> BTSetOp<'a, K, V> {
+        BTSetOp::new(self, other, SetOp::SymmetricDifference)
+    }
+
+    /// move the entries with a key greater than or equal to the given one to a new tree, in O(n)
+    /// both trees are rebuilt balanced
+    pub fn split_off(&mut self, key: &K) -> BTree<K, V> {
+        let mut entries = flatten(self.root.take());
+        let at = entries.partition_point(|(k, _)| *k < *key);
+        let upper = entries.split_off(at);
+        self.root = build(entries);
+        BTree {
+            root: build(upper),
+            balanced: self.balanced,
+        }
+    }
+
+    /// move all the entries of the other tree into this one, in O(n + m), leaving the other empty
+    /// the values of the other tree win for the common keys, and this tree is rebuilt balanced
+    pub fn append(&mut self, other: &mut BTree<K, V>) {
+        let mut mine = flatten(self.root.take()).into_iter().peekable();
+        let mut theirs = flatten(other.root.take()).into_iter().peekable();
+        let mut entries = Vec::with_capacity(mine.len() + theirs.len());
+        loop {
+            let next = match (mine.peek(), theirs.peek()) {
+                (None, None) => break,
+                (Some(_), None) => mine.next(),
+                (None, Some(_)) => theirs.next(),
+                (Some(a), Some(b)) => {
+                    if a.0 < b.0 {
+                        mine.next()
+                    } else {
+                        if a.0 == b.0 {
+                            mine.next();
+                        }
+                        theirs.next()
+                    }
+                }
+            };
+            entries.extend(next);
+        }
+        self.root = build(entries);
     }
 }
 
@@ -72,20 +370,40 @@ impl<'a, K, V> IntoIterator for &'a BTree<K, V> {
     type IntoIter = BTIterator<'a, K, V>;
 
     fn into_iter(self) -> Self::IntoIter {
-        let mut v = vec![];
-        let mut on = &self.root;
-        while let Some(ro) = on {
-            on = &ro.left;
-            v.push(ro.as_ref());
-        }
-
-        BTIterator { stack: v }
+        let mut it = BTIterator {
+            stack: vec![],
+            back: vec![],
+            remaining: size(&self.root),
+        };
+        it.push_left(&self.root);
+        it.push_right(&self.root);
+        it
     }
 }
 
-/// iterator with a stack of nodes to visit
+/// iterator with a stack of nodes to visit from each end, and the number of nodes left between them
 pub struct BTIterator<'a, K, V> {
     stack: Vec<&'a BNode<K, V>>,
+    back: Vec<&'a BNode<K, V>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> BTIterator<'a, K, V> {
+    /// push a node and all its left descendants
+    fn push_left(&mut self, mut on: &'a Option<Box<BNode<K, V>>>) {
+        while let Some(ro) = on {
+            on = &ro.left;
+            self.stack.push(ro);
+        }
+    }
+
+    /// push a node and all its right descendants on the back stack
+    fn push_right(&mut self, mut on: &'a Option<Box<BNode<K, V>>>) {
+        while let Some(ro) = on {
+            on = &ro.right;
+            self.back.push(ro);
+        }
+    }
 }
 
 /// iterator implementation
@@ -93,140 +411,725 @@ impl<'a, K, V> Iterator for BTIterator<'a, K, V> {
     type Item = (&'a K, &'a V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.stack.pop() {
-            None => None,
-            Some(n) => {
-                let mut on = &n.right;
-                while let Some(ro) = on {
-                    on = &ro.left;
-                    self.stack.push(ro);
+        if self.remaining == 0 {
+            return None;
+        }
+        let n = self.stack.pop()?;
+        self.remaining -= 1;
+        self.push_left(&n.right);
+        Some((&n.key, &n.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// iterate from the largest key, stopping where the iteration from the front is
+impl<'a, K, V> DoubleEndedIterator for BTIterator<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let n = self.back.pop()?;
+        self.remaining -= 1;
+        self.push_right(&n.left);
+        Some((&n.key, &n.value))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for BTIterator<'a, K, V> {}
+
+/// in-order iterator over the keys
+pub struct BTKeys<'a, K, V>(BTIterator<'a, K, V>);
+
+impl<'a, K, V> Iterator for BTKeys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for BTKeys<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(k, _)| k)
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for BTKeys<'a, K, V> {}
+
+/// in-order iterator over the values
+pub struct BTValues<'a, K, V>(BTIterator<'a, K, V>);
+
+impl<'a, K, V> Iterator for BTValues<'a, K, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for BTValues<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for BTValues<'a, K, V> {}
+
+/// Turn into an in-order iterator of the owned keys and values
+impl<K, V> IntoIterator for BTree<K, V> {
+    type Item = (K, V);
+    type IntoIter = BTIntoIter<K, V>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        BTIntoIter(flatten(self.root.take()).into_iter())
+    }
+}
+
+/// The set operation a `BTSetOp` performs
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SetOp {
+    Union,
+    Intersection,
+    Difference,
+    SymmetricDifference,
+}
+
+/// lazy set operation, merging the in-order iterators of two trees
+pub struct BTSetOp<'a, K, V> {
+    left: Peekable<BTIterator<'a, K, V>>,
+    right: Peekable<BTIterator<'a, K, V>>,
+    op: SetOp,
+}
+
+impl<'a, K, V> BTSetOp<'a, K, V> {
+    fn new(left: &'a BTree<K, V>, right: &'a BTree<K, V>, op: SetOp) -> BTSetOp<'a, K, V> {
+        BTSetOp {
+            left: left.into_iter().peekable(),
+            right: right.into_iter().peekable(),
+            op,
+        }
+    }
+}
+
+impl<'a, K: PartialOrd, V> Iterator for BTSetOp<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (l, r) = match (self.left.peek(), self.right.peek()) {
+                (None, None) => return None,
+                (None, Some(_))
+                    if self.op == SetOp::Intersection || self.op == SetOp::Difference =>
+                {
+                    return None
+                }
+                (Some(_), None) if self.op == SetOp::Intersection => return None,
+                (Some(_), None) => (true, false),
+                (None, Some(_)) => (false, true),
+                (Some(a), Some(b)) => (a.0 <= b.0, b.0 <= a.0),
+            };
+            // advance the side(s) holding the smallest key, and keep the entry if the operation wants it
+            match (l, r) {
+                (true, true) => {
+                    self.right.next();
+                    let e = self.left.next();
+                    if self.op == SetOp::Union || self.op == SetOp::Intersection {
+                        return e;
+                    }
+                }
+                (true, false) => {
+                    let e = self.left.next();
+                    if self.op != SetOp::Intersection {
+                        return e;
+                    }
+                }
+                _ => {
+                    let e = self.right.next();
+                    if self.op == SetOp::Union || self.op == SetOp::SymmetricDifference {
+                        return e;
+                    }
                 }
-                Some((&n.key, &n.value))
             }
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (l, r) = (self.left.len(), self.right.len());
+        match self.op {
+            SetOp::Union => (max(l, r), Some(l + r)),
+            SetOp::Intersection => (0, Some(l.min(r))),
+            SetOp::Difference => (l.saturating_sub(r), Some(l)),
+            SetOp::SymmetricDifference => (0, Some(l + r)),
+        }
+    }
+}
+
+/// iterator over the owned keys and values of a tree
+pub struct BTIntoIter<K, V>(std::vec::IntoIter<(K, V)>);
+
+impl<K, V> Iterator for BTIntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for BTIntoIter<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back()
+    }
+}
+
+impl<K, V> ExactSizeIterator for BTIntoIter<K, V> {}
+
+/// in-order iterator with mutable values, keeping the key, the value and the right subtree of the nodes to visit
+pub struct BTIterMut<'a, K, V> {
+    stack: Vec<(&'a K, &'a mut V, &'a mut Link<K, V>)>,
+}
+
+impl<'a, K, V> BTIterMut<'a, K, V> {
+    /// push a node and all its left descendants
+    fn push_left(&mut self, mut on: &'a mut Link<K, V>) {
+        while let Some(ro) = on {
+            let BNode {
+                key,
+                value,
+                left,
+                right,
+                ..
+            } = &mut **ro;
+            self.stack.push((key, value, right));
+            on = left;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for BTIterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v, right) = self.stack.pop()?;
+        self.push_left(right);
+        Some((k, v))
+    }
+}
+
+/// An entry of the tree, occupied or vacant
+pub enum Entry<'a, K, V> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// An entry for a key present in the tree
+pub struct OccupiedEntry<'a, K, V> {
+    key: &'a K,
+    value: &'a mut V,
+}
+
+/// An entry for a key absent from the tree
+pub struct VacantEntry<'a, K, V> {
+    key: K,
+    tree: &'a mut BTree<K, V>,
+    /// the way from the root to the empty slot of the key
+    path: Path,
+}
+
+impl<'a, K: Eq + PartialOrd, V> Entry<'a, K, V> {
+    /// the key of the entry
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key,
+            Entry::Vacant(e) => &e.key,
+        }
+    }
+
+    /// the value, inserting the default if vacant
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// the value, inserting the result of the function if vacant
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    /// the value, inserting the default value of the type if vacant
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// modify the value if occupied
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(e) => {
+                f(e.value);
+                Entry::Occupied(e)
+            }
+            e => e,
+        }
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    /// the key of the entry
+    pub fn key(&self) -> &K {
+        self.key
+    }
+
+    /// a reference to the value
+    pub fn get(&self) -> &V {
+        self.value
+    }
+
+    /// a mutable reference to the value
+    pub fn get_mut(&mut self) -> &mut V {
+        self.value
+    }
+
+    /// the mutable reference to the value, with the lifetime of the entry
+    pub fn into_mut(self) -> &'a mut V {
+        self.value
+    }
+
+    /// replace the value, returning the old one
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.value, value)
+    }
+}
+
+impl<'a, K: Eq + PartialOrd, V> VacantEntry<'a, K, V> {
+    /// the key of the entry
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// insert the value, returning a mutable reference to it
+    pub fn insert(self, value: V) -> &'a mut V {
+        let tree = self.tree;
+        let mut path = self.path;
+        let mut parents = vec![];
+        let mut onode = tree.root.take();
+        while let Some(right) = path.pop() {
+            let mut node = onode.expect("path to the vacant slot");
+            onode = take_child(&mut node, right);
+            parents.push((node, right));
+        }
+        let leaf = Box::new(BNode::leaf(self.key, value));
+        let (newroot, path) = reattach(leaf, parents, tree.balanced);
+        &mut follow(tree.root.insert(newroot), path).value
+    }
 }
 
 /// convert from an iterator
 impl<K: Eq + PartialOrd, V> FromIterator<(K, V)> for BTree<K, V> {
     fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let entries: Vec<(K, V)> = iter.into_iter().collect();
+        if entries.windows(2).all(|w| w[0].0 <= w[1].0) {
+            // already sorted: build it balanced in O(n), the last value winning for repeated keys
+            let mut sorted: Vec<(K, V)> = Vec::with_capacity(entries.len());
+            for (k, v) in entries {
+                match sorted.last_mut() {
+                    Some(last) if last.0 == k => last.1 = v,
+                    _ => sorted.push((k, v)),
+                }
+            }
+            return BTree {
+                root: build(sorted),
+                balanced: false,
+            };
+        }
         let mut t = BTree::new();
-        for i in iter {
+        for i in entries {
             t.insert(i.0, i.1);
         }
         t
     }
 }
 
-/// insert key/value in the right node
+/// drop the nodes one at a time: the default recursive drop would overflow the stack on deep trees
+impl<K, V> Drop for BTree<K, V> {
+    fn drop(&mut self) {
+        let mut stack: Vec<Box<BNode<K, V>>> = self.root.take().into_iter().collect();
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.left.take());
+            stack.extend(node.right.take());
+        }
+    }
+}
+
+/// move the entries of the subtree out in order, freeing the nodes on the way
+fn flatten<K, V>(onode: Link<K, V>) -> Vec<(K, V)> {
+    let mut entries = Vec::with_capacity(size(&onode));
+    let mut stack = vec![];
+    let mut on = onode;
+    loop {
+        while let Some(mut n) = on {
+            on = n.left.take();
+            stack.push(n);
+        }
+        match stack.pop() {
+            None => break,
+            Some(mut n) => {
+                on = n.right.take();
+                entries.push((n.key, n.value));
+            }
+        }
+    }
+    entries
+}
+
+/// balanced subtree of the sorted entries, with no repeated keys
+fn build<K, V>(entries: Vec<(K, V)>) -> Link<K, V> {
+    let n = entries.len();
+    build_node(&mut entries.into_iter(), n)
+}
+
+/// balanced subtree of the next n entries: the sizes of the two sides differ by at most one,
+/// so it is a valid AVL tree
+fn build_node<K, V, I: Iterator<Item = (K, V)>>(entries: &mut I, n: usize) -> Link<K, V> {
+    if n == 0 {
+        return None;
+    }
+    let left = build_node(entries, n / 2);
+    let (key, value) = entries.next().expect("n entries left");
+    let right = build_node(entries, n - n / 2 - 1);
+    let mut node = BNode::leaf(key, value);
+    node.left = left;
+    node.right = right;
+    node.update();
+    Some(Box::new(node))
+}
+
+/// insert key/value in the right node, returning the new node and the path from it to the key
 fn insert_into_node<K: Eq + PartialOrd, V>(
     onode: Option<Box<BNode<K, V>>>,
     key: K,
     value: V,
-) -> BNode<K, V> {
-    match onode {
-        None => BNode {
-            key,
-            value,
-            left: None,
-            right: None,
-        },
-        Some(mut node) => {
-            if node.key == key {
-                node.value = value;
-            } else if key < node.key {
-                let left = node.left.take();
-                node.left = Some(Box::new(insert_into_node(left, key, value)));
-            } else {
-                let right = node.right.take();
-                node.right = Some(Box::new(insert_into_node(right, key, value)));
-            };
-            *node
+    balanced: bool,
+) -> (Box<BNode<K, V>>, Path) {
+    let mut parents = vec![];
+    let mut onode = onode;
+    let node = loop {
+        match onode {
+            None => break Box::new(BNode::leaf(key, value)),
+            Some(mut node) => {
+                if node.key == key {
+                    node.value = value;
+                    break node;
+                }
+                let right = key >= node.key;
+                onode = take_child(&mut node, right);
+                parents.push((node, right));
+            }
+        }
+    };
+    reattach(node, parents, balanced)
+}
+
+/// put a node back under the parents taken on the way down to it, fixing them from the bottom,
+/// returning the new root and the path from it to the node
+fn reattach<K, V>(
+    mut node: Box<BNode<K, V>>,
+    mut parents: Vec<(Box<BNode<K, V>>, bool)>,
+    balanced: bool,
+) -> (Box<BNode<K, V>>, Path) {
+    let mut path = vec![];
+    while let Some((mut parent, right)) = parents.pop() {
+        *child(&mut parent, right) = Some(node);
+        path.push(right);
+        node = fix(parent, balanced, Some(&mut path));
+    }
+    (node, path)
+}
+
+/// the descendant at the end of the path
+fn follow<K, V>(mut node: &mut BNode<K, V>, mut path: Path) -> &mut BNode<K, V> {
+    while let Some(right) = path.pop() {
+        node = child(node, right)
+            .as_deref_mut()
+            .expect("path to a descendant");
+    }
+    node
+}
+
+/// search for the given key and return the node holding it if found
+fn search_node_mut<'a, K: Eq + PartialOrd, V>(
+    onode: &'a mut Link<K, V>,
+    key: &K,
+) -> Option<&'a mut BNode<K, V>> {
+    let mut on = onode.as_deref_mut();
+    while let Some(node) = on {
+        if node.key == *key {
+            return Some(node);
         }
+        on = if *key < node.key {
+            node.left.as_deref_mut()
+        } else {
+            node.right.as_deref_mut()
+        };
     }
+    None
 }
 
 /// search for the given key and return a reference to the value if found
 fn search_node<'a, K: Eq + PartialOrd, V>(
-    onode: &'a Option<Box<BNode<K, V>>>,
+    mut onode: &'a Option<Box<BNode<K, V>>>,
     key: &K,
 ) -> Option<&'a V> {
-    match onode {
-        None => None,
-        Some(node) => {
-            if node.key == *key {
-                Some(&node.value)
-            } else if *key < node.key {
-                search_node(&node.left, key)
-            } else {
-                search_node(&node.right, key)
-            }
+    while let Some(node) = onode {
+        if node.key == *key {
+            return Some(&node.value);
         }
+        onode = if *key < node.key {
+            &node.left
+        } else {
+            &node.right
+        };
     }
+    None
 }
 
 /// delete the node for the given key
 fn delete_node<K: Eq + PartialOrd, V>(
     onode: Option<Box<BNode<K, V>>>,
     key: &K,
+    balanced: bool,
 ) -> (Option<Box<BNode<K, V>>>, Option<V>) {
-    match onode {
-        None => (None, None),
-        Some(mut node) => {
-            if node.key == *key {
-                let v = Some(node.value);
-                let n = match (node.left, node.right) {
-                    (None, None) => None,
-                    (Some(n), None) => Some(n),
-                    (None, Some(n)) => Some(n),
-                    (Some(l), Some(r)) => {
-                        let (nr, k, v) = find_next(r);
-                        Some(Box::new(BNode {
-                            key: k,
-                            value: v,
-                            left: Some(l),
-                            right: nr,
-                        }))
-                    }
-                };
-                (n, v)
-            } else if *key < node.key {
-                let left = node.left.take();
-                let (n, v) = delete_node(left, key);
-                node.left = n;
-                (Some(node), v)
-            } else {
-                let right = node.right.take();
-                let (n, v) = delete_node(right, key);
-                node.right = n;
-                (Some(node), v)
+    let mut parents = vec![];
+    let mut onode = onode;
+    let (mut n, v) = loop {
+        match onode {
+            None => break (None, None),
+            Some(mut node) => {
+                if node.key == *key {
+                    let node = *node;
+                    let n = match (node.left, node.right) {
+                        (None, None) => None,
+                        (Some(n), None) => Some(n),
+                        (None, Some(n)) => Some(n),
+                        (Some(l), Some(r)) => {
+                            let (nr, k, v) = find_next(r, balanced);
+                            let mut n = BNode::leaf(k, v);
+                            n.left = Some(l);
+                            n.right = nr;
+                            Some(fix(Box::new(n), balanced, None))
+                        }
+                    };
+                    break (n, Some(node.value));
+                }
+                let right = *key >= node.key;
+                onode = take_child(&mut node, right);
+                parents.push((node, right));
             }
         }
+    };
+    while let Some((mut parent, right)) = parents.pop() {
+        *child(&mut parent, right) = n;
+        n = Some(fix(parent, balanced, None));
     }
+    (n, v)
 }
 
 /// find the next in order node
 fn find_next<K: Eq + PartialOrd, V>(
-    mut onode: Box<BNode<K, V>>,
+    node: Box<BNode<K, V>>,
+    balanced: bool,
 ) -> (Option<Box<BNode<K, V>>>, K, V) {
-    match onode.left {
-        Some(n) => {
-            let (nl, k, v) = find_next(n);
-            onode.left = nl;
-            (Some(onode), k, v)
+    let mut parents = vec![];
+    let mut node = node;
+    while let Some(left) = node.left.take() {
+        parents.push(node);
+        node = left;
+    }
+    let node = *node;
+    let mut n = node.right;
+    while let Some(mut parent) = parents.pop() {
+        parent.left = n;
+        n = Some(fix(parent, balanced, None));
+    }
+    (n, node.key, node.value)
+}
+
+/// the child link on the given side
+fn child<K, V>(node: &mut BNode<K, V>, right: bool) -> &mut Link<K, V> {
+    if right {
+        &mut node.right
+    } else {
+        &mut node.left
+    }
+}
+
+/// detach the child on the given side
+fn take_child<K, V>(node: &mut BNode<K, V>, right: bool) -> Link<K, V> {
+    child(node, right).take()
+}
+
+/// the entry with the greatest key below the given key, or equal to it if inclusive
+fn below<'a, K: PartialOrd, V>(
+    mut onode: &'a Option<Box<BNode<K, V>>>,
+    key: &K,
+    inclusive: bool,
+) -> Option<(&'a K, &'a V)> {
+    let mut found = None;
+    while let Some(node) = onode {
+        if node.key < *key || (inclusive && node.key == *key) {
+            found = Some((&node.key, &node.value));
+            onode = &node.right;
+        } else {
+            onode = &node.left;
+        }
+    }
+    found
+}
+
+/// the entry with the least key above the given key, or equal to it if inclusive
+fn above<'a, K: PartialOrd, V>(
+    mut onode: &'a Option<Box<BNode<K, V>>>,
+    key: &K,
+    inclusive: bool,
+) -> Option<(&'a K, &'a V)> {
+    let mut found = None;
+    while let Some(node) = onode {
+        if node.key > *key || (inclusive && node.key == *key) {
+            found = Some((&node.key, &node.value));
+            onode = &node.left;
+        } else {
+            onode = &node.right;
+        }
+    }
+    found
+}
+
+/// height of a node, 0 if there is none
+fn height<K, V>(onode: &Option<Box<BNode<K, V>>>) -> usize {
+    onode.as_ref().map_or(0, |n| n.height)
+}
+
+/// number of nodes in a subtree
+fn size<K, V>(onode: &Option<Box<BNode<K, V>>>) -> usize {
+    onode.as_ref().map_or(0, |n| n.size)
+}
+
+/// The way from a node to one of its descendants, `true` meaning right, the first step being last
+type Path = Vec<bool>;
+
+/// update the height of a node whose children changed, rebalancing it if needed.
+/// The path to a descendant, if given, is kept pointing to the same node
+fn fix<K, V>(
+    mut node: Box<BNode<K, V>>,
+    balanced: bool,
+    path: Option<&mut Path>,
+) -> Box<BNode<K, V>> {
+    node.update();
+    if balanced {
+        rebalance(node, path)
+    } else {
+        node
+    }
+}
+
+/// restore the AVL invariant at a node whose subtrees are balanced and differ in height by at most 2
+fn rebalance<K, V>(mut node: Box<BNode<K, V>>, mut path: Option<&mut Path>) -> Box<BNode<K, V>> {
+    let b = node.balance();
+    if b > 1 {
+        if node.left.as_ref().map_or(0, |l| l.balance()) < 0 {
+            node.left = node.left.take().map(rotate_left);
+            if let Some(p) = path.as_deref_mut() {
+                rotated_below(p, false, true);
+            }
+        }
+        if let Some(p) = path {
+            rotated(p, false);
+        }
+        rotate_right(node)
+    } else if b < -1 {
+        if node.right.as_ref().map_or(0, |r| r.balance()) > 0 {
+            node.right = node.right.take().map(rotate_right);
+            if let Some(p) = path.as_deref_mut() {
+                rotated_below(p, true, false);
+            }
+        }
+        if let Some(p) = path {
+            rotated(p, true);
+        }
+        rotate_left(node)
+    } else {
+        node
+    }
+}
+
+/// update a path after a rotation at its start, the child on the given side moving up
+fn rotated(path: &mut Path, up: bool) {
+    match path.pop() {
+        // the node itself goes down on the other side
+        None => path.push(!up),
+        // the subtree between the child and the node changes sides
+        Some(first) if first == up => {
+            if path.last() == Some(&!up) {
+                path.pop();
+                path.push(up);
+                path.push(!up);
+            }
+        }
+        // the other subtree goes down with the node
+        Some(first) => {
+            path.push(first);
+            path.push(first);
         }
-        None => (onode.right, onode.key, onode.value),
     }
 }
 
-/// depth of a node
-fn depth<K, V>(onode: &Option<Box<BNode<K, V>>>) -> usize {
-    match onode {
-        None => 0,
-        Some(node) => max(depth(&node.left), depth(&node.right)) + 1,
+/// update a path after a rotation at the child on the given side, its own child on the up side moving up
+fn rotated_below(path: &mut Path, side: bool, up: bool) {
+    if path.last() == Some(&side) {
+        path.pop();
+        rotated(path, up);
+        path.push(side);
     }
 }
 
+/// rotate a node with its left child, which becomes the root of the subtree
+fn rotate_right<K, V>(mut node: Box<BNode<K, V>>) -> Box<BNode<K, V>> {
+    let mut l = node.left.take().expect("rotate right without left child");
+    node.left = l.right.take();
+    node.update();
+    l.right = Some(node);
+    l.update();
+    l
+}
+
+/// rotate a node with its right child, which becomes the root of the subtree
+fn rotate_left<K, V>(mut node: Box<BNode<K, V>>) -> Box<BNode<K, V>> {
+    let mut r = node.right.take().expect("rotate left without right child");
+    node.right = r.left.take();
+    node.update();
+    r.left = Some(node);
+    r.update();
+    r
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct TreeNode {
     pub val: i32,
@@ -372,6 +1275,8 @@ impl Solution {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::Cell;
+    use std::cmp::Ordering;
 
     #[test]
     fn test_basic() {
@@ -422,11 +1327,18 @@ mod tests {
         tree.insert(2, "10");
         tree.insert(0, "00");
         tree.insert(3, "11");
-        let mut it = tree.into_iter();
+        let mut it = tree.iter();
         assert_eq!(Some((&0, &"00")), it.next());
         assert_eq!(Some((&1, &"01")), it.next());
         assert_eq!(Some((&2, &"10")), it.next());
         assert_eq!(Some((&3, &"11")), it.next());
+
+        // without rebalancing, inserting sorted keys gives a linked list
+        let mut tree = BTree::new();
+        for i in 0..100 {
+            tree.insert(i, i);
+        }
+        assert_eq!(100, tree.depth());
     }
 
     #[test]
@@ -439,6 +1351,93 @@ mod tests {
         assert_eq!(None, tree.get(&3));
     }
 
+    #[test]
+    fn test_from_sorted() {
+        let tree = (0..1000).map(|i| (i, i)).collect::<BTree<_, _>>();
+        check_avl(&tree.root);
+        assert_eq!(1000, tree.len());
+        assert_eq!(10, tree.depth());
+        // repeated keys keep the last value
+        let tree = BTree::from_iter(vec![(1, 'a'), (2, 'b'), (2, 'c'), (3, 'd')]);
+        check_avl(&tree.root);
+        assert_eq!(
+            vec![(&1, &'a'), (&2, &'c'), (&3, &'d')],
+            tree.iter().collect::<Vec<_>>()
+        );
+        // unsorted input is inserted in order
+        let tree = BTree::from_iter(vec![(3, 'a'), (1, 'b'), (2, 'c')]);
+        assert_eq!(3, tree.depth());
+        assert_eq!(vec![&1, &2, &3], tree.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let a = (0..20)
+            .step_by(2)
+            .map(|i| (i, 'a'))
+            .collect::<BTree<_, _>>();
+        let b = (0..20)
+            .step_by(3)
+            .map(|i| (i, 'b'))
+            .collect::<BTree<_, _>>();
+        let union = a.union(&b).collect::<Vec<_>>();
+        assert_eq!(13, union.len());
+        assert_eq!(&(&0, &'a'), &union[0]);
+        assert_eq!(&(&3, &'b'), &union[2]);
+        assert_eq!(vec![0, 2, 3, 4, 6, 8, 9], keys(a.union(&b).take(7)));
+        assert_eq!(vec![0, 6, 12, 18], keys(a.intersection(&b)));
+        assert!(a.intersection(&b).all(|(_, v)| *v == 'a'));
+        assert_eq!(vec![2, 4, 8, 10, 14, 16], keys(a.difference(&b)));
+        assert_eq!(vec![3, 9, 15], keys(b.difference(&a)));
+        assert_eq!(
+            vec![2, 3, 4, 8, 9, 10, 14, 15, 16],
+            keys(a.symmetric_difference(&b))
+        );
+
+        let empty = BTree::new();
+        assert_eq!(10, a.union(&empty).count());
+        assert_eq!(10, empty.union(&a).count());
+        assert_eq!(0, a.intersection(&empty).count());
+        assert_eq!(0, empty.difference(&a).count());
+        assert_eq!(10, a.symmetric_difference(&empty).count());
+        assert_eq!((10, Some(17)), a.union(&b).size_hint());
+        assert_eq!((0, Some(7)), a.intersection(&b).size_hint());
+    }
+
+    #[test]
+    fn test_split_append() {
+        let mut tree = BTree::new();
+        for i in 0..100 {
+            tree.insert(i, i);
+        }
+        assert_eq!(100, tree.depth());
+        let mut upper = tree.split_off(&40);
+        check_avl(&tree.root);
+        check_avl(&upper.root);
+        assert_eq!((0..40).collect::<Vec<_>>(), keys(tree.iter()));
+        assert_eq!((40..100).collect::<Vec<_>>(), keys(upper.iter()));
+        assert!(tree.depth() <= 6);
+        assert_eq!(0, tree.split_off(&100).len());
+        assert_eq!(40, tree.split_off(&-1).len());
+        assert!(tree.is_empty());
+
+        let mut other = (30..50).map(|i| (i, -i)).collect::<BTree<_, _>>();
+        upper.append(&mut other);
+        assert!(other.is_empty());
+        check_avl(&upper.root);
+        assert_eq!((30..100).collect::<Vec<_>>(), keys(upper.iter()));
+        assert_eq!(Some(&-45), upper.get(&45));
+        assert_eq!(Some(&55), upper.get(&55));
+        assert_eq!(Some(&-30), upper.get(&30));
+
+        let mut balanced = BTree::new_balanced();
+        balanced.append(&mut upper);
+        assert_eq!(70, balanced.len());
+        balanced.insert(200, 0);
+        assert!(balanced.is_balanced());
+        check_avl(&balanced.root);
+    }
+
     #[test]
     fn test_wikipedia() {
         // <https://en.wikipedia.org/wiki/Binary_search_tree>
@@ -454,8 +1453,20 @@ mod tests {
         tree.insert(13, "13");
         assert_eq!(9, tree.len());
         assert_eq!(4, tree.depth());
+        // the shape of the figure
+        let shape: BTree<i32, &str> =
+            BTree::parse("[8,3,10,1,6,null,14,null,null,4,7,13]").unwrap();
+        let level_keys = |t: Vec<Option<(&i32, _)>>| {
+            t.into_iter()
+                .map(|e| e.map(|(k, _)| *k))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            level_keys(shape.to_level_order()),
+            level_keys(tree.to_level_order())
+        );
 
-        let mut it = tree.into_iter();
+        let mut it = tree.iter();
         assert_eq!(Some((&1, &"1")), it.next());
         assert_eq!(Some((&3, &"3")), it.next());
         assert_eq!(Some((&4, &"4")), it.next());
@@ -467,55 +1478,432 @@ mod tests {
         assert_eq!(Some((&14, &"14")), it.next());
     }
 
+    /// the keys returned by an iterator
+    fn keys<'a, V: 'a, I: Iterator<Item = (&'a i32, V)>>(it: I) -> Vec<i32> {
+        it.map(|(k, _)| *k).collect()
+    }
+
+    #[test]
+    fn test_range() {
+        let tree = BTree::from_iter((0..20).map(|i| (i * 2, i)));
+        assert_eq!(vec![4, 6, 8], keys(tree.range(4..10)));
+        assert_eq!(vec![4, 6, 8, 10], keys(tree.range(3..=10)));
+        assert_eq!(vec![34, 36, 38], keys(tree.range(33..)));
+        assert_eq!(vec![0, 2], keys(tree.range(..4)));
+        assert_eq!(vec![0, 2, 4], keys(tree.range(..=4)));
+        assert_eq!(20, tree.range(..).count());
+        assert_eq!(
+            vec![6, 8],
+            keys(tree.range((Bound::Excluded(4), Bound::Included(8))))
+        );
+        assert!(keys(tree.range(5..5)).is_empty());
+        assert!(keys(tree.range(40..)).is_empty());
+        assert_eq!(Some((&12, &6)), tree.range(11..).next());
+
+        // the range starts with at most one node per level, not the whole tree
+        let tree = BTree::from_iter((0..1000).map(|i| (i * 7919 % 1000, i)));
+        let range = tree.range(500..510);
+        assert!(range.stack.len() <= tree.depth());
+        assert!(range.back.len() <= tree.depth());
+        assert_eq!((500..510).collect::<Vec<_>>(), keys(range));
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut tree = BTree::new_balanced();
+        assert_eq!(None, tree.first());
+        assert_eq!(None, tree.last());
+        assert_eq!(None, tree.floor(&1));
+        for k in [10, 20, 30, 40, 50].iter() {
+            tree.insert(*k, k.to_string());
+        }
+        let key = |e: Option<(&i32, &String)>| e.map(|(k, _)| *k);
+        assert_eq!(Some(10), key(tree.first()));
+        assert_eq!(Some(50), key(tree.last()));
+        assert_eq!(Some(&String::from("50")), tree.last().map(|(_, v)| v));
+
+        assert_eq!(Some(30), key(tree.floor(&30)));
+        assert_eq!(Some(30), key(tree.floor(&35)));
+        assert_eq!(None, key(tree.floor(&5)));
+        assert_eq!(Some(50), key(tree.floor(&500)));
+        assert_eq!(Some(30), key(tree.ceiling(&30)));
+        assert_eq!(Some(40), key(tree.ceiling(&35)));
+        assert_eq!(None, key(tree.ceiling(&55)));
+        assert_eq!(Some(10), key(tree.ceiling(&-5)));
+
+        assert_eq!(Some(40), key(tree.successor(&30)));
+        assert_eq!(Some(40), key(tree.successor(&35)));
+        assert_eq!(None, key(tree.successor(&50)));
+        assert_eq!(Some(20), key(tree.predecessor(&30)));
+        assert_eq!(Some(30), key(tree.predecessor(&35)));
+        assert_eq!(None, key(tree.predecessor(&10)));
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut tree = BTree::from_iter((0..10).map(|i| (i, i)));
+        *tree.get_mut(&3).unwrap() = 30;
+        assert_eq!(Some(&30), tree.get(&3));
+        assert_eq!(None, tree.get_mut(&10));
+
+        for (k, v) in tree.iter_mut() {
+            *v += k * 100;
+        }
+        assert_eq!(Some(&330), tree.get(&3));
+        assert_eq!(Some(&909), tree.get(&9));
+        assert_eq!(
+            (0..10).collect::<Vec<_>>(),
+            tree.iter_mut().map(|(k, _)| *k).collect::<Vec<_>>()
+        );
+        assert_eq!(None, BTree::<i32, i32>::new().iter_mut().next());
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut counts = BTree::new_balanced();
+        for w in "the cat and the dog and the bird".split(' ') {
+            *counts.entry(w).or_insert(0) += 1;
+        }
+        assert_eq!(Some(&3), counts.get(&"the"));
+        assert_eq!(Some(&2), counts.get(&"and"));
+        assert_eq!(Some(&1), counts.get(&"dog"));
+        assert_eq!(5, counts.len());
+
+        counts.entry("cat").and_modify(|c| *c += 10).or_insert(0);
+        counts.entry("cow").and_modify(|c| *c += 10).or_insert(7);
+        assert_eq!(Some(&11), counts.get(&"cat"));
+        assert_eq!(Some(&7), counts.get(&"cow"));
+        assert_eq!(0, *counts.entry("eel").or_default());
+        assert_eq!(&"fox", counts.entry("fox").key());
+
+        let mut calls = 0;
+        counts.entry("the").or_insert_with(|| {
+            calls += 1;
+            0
+        });
+        assert_eq!(0, calls);
+        match counts.entry("the") {
+            Entry::Occupied(mut e) => {
+                assert_eq!(&3, e.get());
+                assert_eq!(3, e.insert(4));
+                *e.get_mut() += 1;
+            }
+            Entry::Vacant(_) => panic!("the is in the tree"),
+        }
+        assert_eq!(Some(&5), counts.get(&"the"));
+        match counts.entry("zebra") {
+            Entry::Vacant(e) => assert_eq!(&"zebra", e.key()),
+            Entry::Occupied(_) => panic!("zebra is not in the tree"),
+        }
+    }
+
+    /// a key counting the comparisons made with it
+    #[derive(Debug)]
+    struct Counted<'a>(i32, &'a Cell<usize>);
+
+    impl PartialEq for Counted<'_> {
+        fn eq(&self, other: &Self) -> bool {
+            self.1.set(self.1.get() + 1);
+            self.0 == other.0
+        }
+    }
+
+    impl Eq for Counted<'_> {}
+
+    impl PartialOrd for Counted<'_> {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            self.1.set(self.1.get() + 1);
+            self.0.partial_cmp(&other.0)
+        }
+    }
+
+    #[test]
+    fn test_entry_comparisons() {
+        let count = Cell::new(0);
+        let mut tree = BTree::new_balanced();
+        for k in 0..1000 {
+            *tree.entry(Counted(k * 2, &count)).or_insert(0) += 1;
+        }
+        let depth = tree.depth();
+        assert_eq!(10, depth);
+        // one comparison for equality and one for the side, at each level of a single walk
+        for k in 0..2000 {
+            count.set(0);
+            *tree.entry(Counted(k, &count)).or_insert(0) += 1;
+            assert!(
+                count.get() <= 2 * depth,
+                "{} comparisons for {}",
+                count.get(),
+                k
+            );
+        }
+        assert_eq!(2000, tree.len());
+        // the even keys were counted twice
+        assert_eq!(3000, tree.values().sum::<i32>());
+        check_avl(&tree.root);
+    }
+
+    #[test]
+    fn test_entry_rebalancing() {
+        // the reference returned by a vacant entry follows the node through the rotations
+        for balanced in [false, true].iter() {
+            let mut tree = if *balanced {
+                BTree::new_balanced()
+            } else {
+                BTree::new()
+            };
+            for i in 0..500 {
+                let k = i * 7919 % 500;
+                *tree.entry(k).or_insert(0) += k;
+                *tree.entry(i / 2).or_insert(0) += 1;
+            }
+            for (k, v) in tree.iter() {
+                let halves = if *k < 250 { 2 } else { 0 };
+                assert_eq!(k + halves, *v, "key {}", k);
+            }
+            for k in (500..1000).rev() {
+                *tree.entry(k).or_insert(k) += 1;
+                assert_eq!(Some(&(k + 1)), tree.get(&k));
+            }
+            assert_eq!(1000, tree.len());
+        }
+    }
+
+    #[test]
+    fn test_rank_select() {
+        let mut tree = BTree::new();
+        assert_eq!(0, tree.rank(&5));
+        assert_eq!(None, tree.select(0));
+        for i in 0..100 {
+            let k = i * 7919 % 100;
+            tree.insert(k * 10, k);
+        }
+        assert_eq!(100, tree.len());
+        assert_eq!(0, tree.rank(&0));
+        assert_eq!(1, tree.rank(&1));
+        assert_eq!(50, tree.rank(&500));
+        assert_eq!(51, tree.rank(&501));
+        assert_eq!(100, tree.rank(&5000));
+        assert_eq!(Some((&0, &0)), tree.select(0));
+        assert_eq!(Some((&420, &42)), tree.select(42));
+        assert_eq!(Some((&990, &99)), tree.select(99));
+        assert_eq!(None, tree.select(100));
+
+        // the 90th percentile, after deleting the odd keys
+        for k in (0..100).filter(|k| k % 2 == 1) {
+            tree.delete(&(k * 10));
+        }
+        assert_eq!(50, tree.len());
+        assert_eq!(Some((&900, &90)), tree.select(tree.len() * 9 / 10));
+        assert_eq!(45, tree.rank(&900));
+        for (i, (k, _)) in tree.iter().enumerate() {
+            assert_eq!(i, tree.rank(k));
+            assert_eq!(Some(k), tree.select(i).map(|(k, _)| k));
+        }
+
+        let mut tree = BTree::new_balanced();
+        for i in 0..1000 {
+            tree.insert(i, ());
+            assert_eq!(i + 1, tree.len());
+        }
+        check_avl(&tree.root);
+        assert_eq!(Some((&700, &())), tree.select(700));
+        assert_eq!(700, tree.rank(&700));
+    }
+
+    #[test]
+    fn test_iterators() {
+        let tree = BTree::from_iter((0..10).map(|i| ((i * 7) % 10, i.to_string())));
+        assert_eq!(
+            (0..10).rev().collect::<Vec<_>>(),
+            tree.keys().rev().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(&String::from("7")), tree.values().nth(9));
+        assert_eq!(10, tree.iter().len());
+        assert_eq!(10, tree.values().len());
+
+        // both ends meet without returning an entry twice
+        let mut it = tree.iter();
+        assert_eq!(Some((&0, &String::from("0"))), it.next());
+        assert_eq!(Some((&9, &String::from("7"))), it.next_back());
+        assert_eq!(8, it.len());
+        assert_eq!(vec![1, 8, 2, 7, 3, 6, 4, 5], {
+            let mut v = vec![];
+            while let (Some((a, _)), Some((b, _))) = (it.next(), it.next_back()) {
+                v.push(*a);
+                v.push(*b);
+            }
+            v
+        });
+        assert_eq!(0, it.len());
+        assert_eq!(None, it.next());
+        assert_eq!(None, it.next_back());
+
+        assert_eq!(vec![6, 5, 4, 3], keys(tree.range(3..7).rev()));
+        assert_eq!(4, tree.range(3..7).len());
+        assert_eq!(
+            0,
+            tree.range((Bound::Included(7), Bound::Excluded(3))).len()
+        );
+        assert_eq!(3, tree.range(..=2).len());
+
+        let owned = tree.into_iter().collect::<Vec<(i32, String)>>();
+        assert_eq!((0, String::from("0")), owned[0]);
+        assert_eq!((3, String::from("9")), owned[3]);
+        let tree = BTree::from_iter(owned);
+        let mut it = tree.into_iter();
+        assert_eq!(10, it.len());
+        assert_eq!(Some((9, String::from("7"))), it.next_back());
+        assert_eq!(
+            vec![0, 1, 2],
+            it.take(3).map(|(k, _)| k).collect::<Vec<_>>()
+        );
+        assert_eq!(0, BTree::<u8, u8>::new().into_iter().len());
+    }
+
+    /// check the keys are ordered and the node is AVL balanced, returning its height
+    fn check_avl<K: PartialOrd, V>(onode: &Option<Box<BNode<K, V>>>) -> usize {
+        match onode {
+            None => 0,
+            Some(node) => {
+                assert!(node.left.as_ref().is_none_or(|l| l.key < node.key));
+                assert!(node.right.as_ref().is_none_or(|r| r.key > node.key));
+                let (hl, hr) = (check_avl(&node.left), check_avl(&node.right));
+                assert!(hl.abs_diff(hr) <= 1);
+                assert_eq!(max(hl, hr) + 1, node.height);
+                assert_eq!(size(&node.left) + size(&node.right) + 1, node.size);
+                node.height
+            }
+        }
+    }
+
+    #[test]
+    fn test_balanced() {
+        let mut tree = BTree::new_balanced();
+        assert!(tree.is_balanced());
+        for i in 0..7 {
+            tree.insert(i, i);
+        }
+        assert_eq!(3, tree.depth());
+        let keys: Vec<_> = tree.keys().copied().collect();
+        assert_eq!((0..7).collect::<Vec<_>>(), keys);
+
+        let n = 100_000;
+        let mut tree = BTree::new_balanced();
+        for i in 0..n {
+            tree.insert(i, i * 2);
+        }
+        // an AVL tree is at most 1.44 times deeper than a perfect tree
+        let bound = (1.44 * ((n + 2) as f64).log2()) as usize;
+        assert!(tree.depth() <= bound, "depth {}", tree.depth());
+        assert_eq!(17, tree.depth());
+        check_avl(&tree.root);
+        assert_eq!(n, tree.len());
+        assert_eq!(Some(&20_000), tree.get(&10_000));
+
+        for i in (0..n).filter(|i| i % 3 != 0) {
+            assert_eq!(Some(i * 2), tree.delete(&i));
+        }
+        check_avl(&tree.root);
+        assert_eq!(None, tree.get(&10_000));
+        assert_eq!(None, tree.get(&10_001));
+        assert_eq!(Some(&20_004), tree.get(&10_002));
+        assert_eq!(n / 3 + 1, tree.len());
+        assert!(tree.depth() <= bound);
+    }
+
+    #[test]
+    fn test_balanced_shuffled() {
+        let mut tree = BTree::new_balanced();
+        let mut plain = BTree::new();
+        // 7919 is prime, so this visits all keys below 1000 in a scrambled order
+        for i in 0..1000 {
+            let k = i * 7919 % 1000;
+            tree.insert(k, i);
+            plain.insert(k, i);
+            check_avl(&tree.root);
+        }
+        assert_eq!(
+            plain.iter().collect::<Vec<_>>(),
+            tree.iter().collect::<Vec<_>>()
+        );
+        for i in 0..1000 {
+            let k = i * 7919 % 1000;
+            if k % 2 == 0 {
+                assert_eq!(Some(i), tree.delete(&k));
+                assert_eq!(None, tree.delete(&k));
+                check_avl(&tree.root);
+            }
+        }
+        assert_eq!(500, tree.len());
+        assert!(tree.get(&999).is_some());
+        assert_eq!(None, tree.get(&998));
+    }
+
+    #[test]
+    fn test_deep() {
+        // sequential inserts into an unbalanced tree walk the whole chain each time: 20 000 of them
+        // take about 20s in a debug build and 4s in release, so a million would take hours.
+        // Insert fewer, then build the chain a million would leave directly; the insert, entry and
+        // delete below still walk it at full depth, and dropping it goes through Drop
+        let mut tree = BTree::new();
+        for k in 0..3_000 {
+            tree.insert(k, k);
+        }
+        assert!(!tree.is_balanced());
+        assert_eq!(3_000, tree.depth());
+        drop(tree);
+
+        let n = 1_000_000;
+        let mut root: Link<usize, usize> = None;
+        for k in (0..n).rev() {
+            let mut node = BNode::leaf(k, k);
+            node.right = root;
+            node.update();
+            root = Some(Box::new(node));
+        }
+        let mut tree = BTree {
+            root,
+            balanced: false,
+        };
+        assert_eq!(n, tree.depth());
+        assert_eq!(Some(&(n - 1)), tree.get(&(n - 1)));
+        tree.insert(n, n);
+        assert_eq!(n + 1, tree.depth());
+        assert_eq!(Some(n / 2), tree.delete(&(n / 2)));
+        assert_eq!(None, tree.delete(&(n / 2)));
+        *tree.entry(n + 1).or_insert(0) += 1;
+        assert_eq!(Some(&1), tree.get(&(n + 1)));
+        assert_eq!(n + 1, tree.iter().count());
+        assert_eq!(Some((&(n / 2 + 1), &(n / 2 + 1))), tree.ceiling(&(n / 2)));
+        drop(tree);
+
+        let mut tree = BTree::new_balanced();
+        for k in 0..n {
+            tree.insert(k, k);
+        }
+        assert_eq!(n, tree.len());
+        assert_eq!(20, tree.depth());
+        for k in (0..n).step_by(2) {
+            tree.delete(&k);
+        }
+        assert_eq!(n / 2, tree.len());
+    }
+
     #[test]
     fn test_get_minimum_difference() {
-        let mut root = TreeNode::new(4);
-        let mut c1 = TreeNode::new(2);
-        let c2 = TreeNode::new(6);
-        let c12 = TreeNode::new(1);
-        let c13 = TreeNode::new(3);
-        c1.left = Some(Rc::new(RefCell::new(c12)));
-        c1.right = Some(Rc::new(RefCell::new(c13)));
-        root.left = Some(Rc::new(RefCell::new(c1)));
-        root.right = Some(Rc::new(RefCell::new(c2)));
-        assert_eq!(1, get_minimum_difference(Some(Rc::new(RefCell::new(root)))));
-
-        let mut root = TreeNode::new(1);
-        let c1 = TreeNode::new(0);
-        let mut c2 = TreeNode::new(48);
-        let c12 = TreeNode::new(12);
-        let c13 = TreeNode::new(49);
-        c2.left = Some(Rc::new(RefCell::new(c12)));
-        c2.right = Some(Rc::new(RefCell::new(c13)));
-        root.left = Some(Rc::new(RefCell::new(c1)));
-        root.right = Some(Rc::new(RefCell::new(c2)));
-        assert_eq!(1, get_minimum_difference(Some(Rc::new(RefCell::new(root)))));
+        let root = TreeNode::parse("[4,2,6,1,3]").unwrap();
+        assert_eq!(1, get_minimum_difference(root));
+        let root = TreeNode::parse("[1,0,48,null,null,12,49]").unwrap();
+        assert_eq!(1, get_minimum_difference(root));
     }
 
     #[test]
     fn test_max_level_sum() {
-        let mut root = TreeNode::new(1);
-        let mut c1 = TreeNode::new(7);
-        let c2 = TreeNode::new(0);
-        let c12 = TreeNode::new(7);
-        let c13 = TreeNode::new(-8);
-        c1.left = Some(Rc::new(RefCell::new(c12)));
-        c1.right = Some(Rc::new(RefCell::new(c13)));
-        root.left = Some(Rc::new(RefCell::new(c1)));
-        root.right = Some(Rc::new(RefCell::new(c2)));
-        assert_eq!(2, max_level_sum(Some(Rc::new(RefCell::new(root)))));
-
-        let mut root = TreeNode::new(989);
-        let mut c2 = TreeNode::new(10250);
-        let c12 = TreeNode::new(98693);
-        let mut c13 = TreeNode::new(-89388);
-        let c131 = TreeNode::new(-32127);
-        c13.right = Some(Rc::new(RefCell::new(c131)));
-        c2.left = Some(Rc::new(RefCell::new(c12)));
-        c2.right = Some(Rc::new(RefCell::new(c13)));
-
-        root.right = Some(Rc::new(RefCell::new(c2)));
-        assert_eq!(2, max_level_sum(Some(Rc::new(RefCell::new(root)))));
+        let root = TreeNode::parse("[1,7,0,7,-8]").unwrap();
+        assert_eq!(2, max_level_sum(root));
+        let root = TreeNode::parse("[989,null,10250,98693,-89388,null,null,null,-32127]").unwrap();
+        assert_eq!(2, max_level_sum(root));
     }
 
     #[test]
@@ -0,0 +1,299 @@
+//! LeetCode-style level-order arrays: `[1,null,2,3]` lists the nodes level by level, each
+//! non-null node taking the next two entries as its children
+use super::{BNode, BTree, Link, TreeNode};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// Error returned when a level order cannot be parsed or does not describe a tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelOrderError {
+    /// the text is not enclosed in square brackets
+    Brackets,
+    /// an entry is neither null nor a value
+    Value { index: usize, text: String },
+    /// the keys are not in search tree order
+    Order,
+}
+
+impl Display for LevelOrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelOrderError::Brackets => write!(f, "expected a list in square brackets"),
+            LevelOrderError::Value { index, text } => {
+                write!(f, "cannot parse '{}' at index {}", text, index)
+            }
+            LevelOrderError::Order => write!(f, "the keys are not in search tree order"),
+        }
+    }
+}
+
+impl Error for LevelOrderError {}
+
+/// parse the bracket form, like `[1,null,2,3]`
+pub fn parse_level_order<T: FromStr>(text: &str) -> Result<Vec<Option<T>>, LevelOrderError> {
+    let inner = text
+        .trim()
+        .strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+        .ok_or(LevelOrderError::Brackets)?
+        .trim();
+    if inner.is_empty() {
+        return Ok(vec![]);
+    }
+    inner
+        .split(',')
+        .map(str::trim)
+        .enumerate()
+        .map(|(index, t)| match t {
+            "null" => Ok(None),
+            _ => t.parse().map(Some).map_err(|_| LevelOrderError::Value {
+                index,
+                text: t.to_string(),
+            }),
+        })
+        .collect()
+}
+
+/// format in the bracket form, like `[1,null,2,3]`
+pub fn format_level_order<T: Display>(values: &[Option<T>]) -> String {
+    let entries = values
+        .iter()
+        .map(|v| match v {
+            Some(v) => v.to_string(),
+            None => String::from("null"),
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", entries.join(","))
+}
+
+impl TreeNode {
+    /// build the tree described by the level order, ignoring the entries left without a parent
+    pub fn from_level_order(values: &[Option<i32>]) -> Option<Rc<RefCell<TreeNode>>> {
+        let slots = children_slots(values);
+        let mut nodes: Vec<Option<Rc<RefCell<TreeNode>>>> = vec![None; values.len()];
+        // children come after their parent, so build from the end
+        for i in (0..values.len()).rev() {
+            if let Some(v) = values[i] {
+                let mut node = TreeNode::new(v);
+                let [l, r] = slots[i];
+                node.left = l.and_then(|c| nodes[c].take());
+                node.right = r.and_then(|c| nodes[c].take());
+                nodes[i] = Some(Rc::new(RefCell::new(node)));
+            }
+        }
+        nodes.into_iter().next().flatten()
+    }
+
+    /// the level order of the tree, without trailing nulls
+    pub fn to_level_order(root: &Option<Rc<RefCell<TreeNode>>>) -> Vec<Option<i32>> {
+        level_order(root.clone(), |n| {
+            let n = n.borrow();
+            (n.val, [n.left.clone(), n.right.clone()])
+        })
+    }
+
+    /// build the tree from the bracket form, like `[1,null,2,3]`
+    pub fn parse(text: &str) -> Result<Option<Rc<RefCell<TreeNode>>>, LevelOrderError> {
+        Ok(TreeNode::from_level_order(&parse_level_order(text)?))
+    }
+}
+
+impl<K: Eq + PartialOrd + Clone, V: Clone> BTree<K, V> {
+    /// build the tree with exactly the shape of the level order, ignoring the entries left
+    /// without a parent; the tree does not rebalance
+    pub fn from_level_order(entries: &[Option<(K, V)>]) -> Result<BTree<K, V>, LevelOrderError> {
+        let slots = children_slots(entries);
+        let mut nodes: Vec<Link<K, V>> = (0..entries.len()).map(|_| None).collect();
+        for i in (0..entries.len()).rev() {
+            if let Some((k, v)) = &entries[i] {
+                let mut node = BNode::leaf(k.clone(), v.clone());
+                let [l, r] = slots[i];
+                node.left = l.and_then(|c| nodes[c].take());
+                node.right = r.and_then(|c| nodes[c].take());
+                node.update();
+                nodes[i] = Some(Box::new(node));
+            }
+        }
+        let tree = BTree {
+            root: nodes.into_iter().next().flatten(),
+            balanced: false,
+        };
+        let mut keys = tree.keys();
+        let mut prev = keys.next();
+        for k in keys {
+            if prev.is_some_and(|p| p.partial_cmp(k) != Some(Ordering::Less)) {
+                return Err(LevelOrderError::Order);
+            }
+            prev = Some(k);
+        }
+        Ok(tree)
+    }
+}
+
+impl<K: Eq + PartialOrd + Clone + FromStr, V: Clone + Default> BTree<K, V> {
+    /// build the tree from the bracket form of its keys, like `[2,1,3]`, each with the default value
+    pub fn parse(text: &str) -> Result<BTree<K, V>, LevelOrderError> {
+        let entries = parse_level_order::<K>(text)?
+            .into_iter()
+            .map(|k| k.map(|k| (k, V::default())))
+            .collect::<Vec<_>>();
+        BTree::from_level_order(&entries)
+    }
+}
+
+impl<K, V> BTree<K, V> {
+    /// the level order of the entries, without trailing nulls
+    pub fn to_level_order(&self) -> Vec<Option<(&K, &V)>> {
+        level_order(self.root.as_deref(), |n| {
+            ((&n.key, &n.value), [n.left.as_deref(), n.right.as_deref()])
+        })
+    }
+}
+
+/// index of the left and right children of each entry: each non-null entry reached from the
+/// root takes the next two entries, in order
+fn children_slots<T>(values: &[Option<T>]) -> Vec<[Option<usize>; 2]> {
+    let mut slots = vec![[None, None]; values.len()];
+    let mut next = 1;
+    for i in 0..values.len() {
+        if values[i].is_none() || (i > 0 && i >= next) {
+            continue;
+        }
+        for slot in slots[i].iter_mut() {
+            if next < values.len() && values[next].is_some() {
+                *slot = Some(next);
+            }
+            next += 1;
+        }
+    }
+    slots
+}
+
+/// breadth first walk, giving the value of each node and null for each missing child
+fn level_order<N, T>(root: Option<N>, node: impl Fn(&N) -> (T, [Option<N>; 2])) -> Vec<Option<T>> {
+    let mut out = vec![];
+    let mut queue = VecDeque::new();
+    queue.push_back(root);
+    while let Some(on) = queue.pop_front() {
+        match on {
+            None => out.push(None),
+            Some(n) => {
+                let (v, children) = node(&n);
+                out.push(Some(v));
+                queue.extend(children);
+            }
+        }
+    }
+    while let Some(None) = out.last() {
+        out.pop();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Ok(vec![Some(1), None, Some(2), Some(3)]),
+            parse_level_order::<i32>("[1,null,2,3]")
+        );
+        assert_eq!(
+            Ok(vec![Some(-1), None]),
+            parse_level_order::<i32>(" [ -1 , null ] ")
+        );
+        assert_eq!(Ok(vec![]), parse_level_order::<i32>("[]"));
+        assert_eq!(
+            Err(LevelOrderError::Brackets),
+            parse_level_order::<i32>("1,2")
+        );
+        assert_eq!(
+            Err(LevelOrderError::Value {
+                index: 1,
+                text: String::from("x")
+            }),
+            parse_level_order::<i32>("[1,x]")
+        );
+        assert_eq!(
+            "[1,null,2,3]",
+            format_level_order(&[Some(1), None, Some(2), Some(3)])
+        );
+    }
+
+    #[test]
+    fn test_tree_node() {
+        let root = TreeNode::parse("[1,null,2,3]").unwrap().unwrap();
+        assert_eq!(1, root.borrow().val);
+        assert!(root.borrow().left.is_none());
+        let right = root.borrow().right.clone().unwrap();
+        assert_eq!(2, right.borrow().val);
+        assert_eq!(3, right.borrow().left.as_ref().unwrap().borrow().val);
+
+        for text in [
+            "[1,null,2,3]",
+            "[5,3,6,2,4,null,7]",
+            "[1,2,null,3,null,4]",
+            "[]",
+        ] {
+            let values = parse_level_order(text).unwrap();
+            let tree = TreeNode::from_level_order(&values);
+            assert_eq!(values, TreeNode::to_level_order(&tree));
+        }
+        // trailing nulls and entries without a parent are dropped
+        let tree = TreeNode::from_level_order(&[Some(1), None, Some(2), None, None, Some(9)]);
+        assert_eq!(
+            vec![Some(1), None, Some(2)],
+            TreeNode::to_level_order(&tree)
+        );
+        assert_eq!(None, TreeNode::from_level_order(&[None, Some(1)]));
+    }
+
+    #[test]
+    fn test_btree() {
+        let entries = parse_level_order::<i32>("[4,2,6,1,null,5]")
+            .unwrap()
+            .into_iter()
+            .map(|k| k.map(|k| (k, k * 10)))
+            .collect::<Vec<_>>();
+        let tree = BTree::from_level_order(&entries).unwrap();
+        assert_eq!(vec![&1, &2, &4, &5, &6], tree.keys().collect::<Vec<_>>());
+        assert_eq!(3, tree.depth());
+        assert_eq!(Some(&50), tree.get(&5));
+        assert_eq!(
+            vec![
+                Some((&4, &40)),
+                Some((&2, &20)),
+                Some((&6, &60)),
+                Some((&1, &10)),
+                None,
+                Some((&5, &50))
+            ],
+            tree.to_level_order()
+        );
+        assert_eq!(
+            LevelOrderError::Order,
+            BTree::from_level_order(&[Some((1, ())), Some((2, ()))]).unwrap_err()
+        );
+        let empty: BTree<i32, ()> = BTree::from_level_order(&[]).unwrap();
+        assert!(empty.to_level_order().is_empty());
+
+        let tree: BTree<i32, i32> = BTree::parse("[4,2,6,1,null,5]").unwrap();
+        assert_eq!(vec![&1, &2, &4, &5, &6], tree.keys().collect::<Vec<_>>());
+        assert_eq!(Some(&0), tree.get(&5));
+        assert_eq!(
+            Err(LevelOrderError::Order),
+            BTree::<i32, ()>::parse("[1,2]").map(|t| t.len())
+        );
+        assert_eq!(
+            Err(LevelOrderError::Brackets),
+            BTree::<i32, ()>::parse("1").map(|t| t.len())
+        );
+    }
+}
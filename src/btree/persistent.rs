@@ -0,0 +1,403 @@
+//! Persistent Binary Search Tree: updates return a new version sharing the unchanged subtrees
+use std::cmp::max;
+use std::iter::FromIterator;
+use std::rc::Rc;
+
+/// A persistent, self-balancing (AVL) Binary Search Tree
+/// `insert` and `delete` leave the tree untouched and return a new version, that shares all
+/// the nodes outside of the path to the key with the old one: both versions stay readable
+#[derive(Debug)]
+pub struct PersistentBTree<K, V> {
+    root: PLink<K, V>,
+}
+
+/// A possibly empty shared subtree
+type PLink<K, V> = Option<Rc<PNode<K, V>>>;
+
+/// A node in the tree, never modified once built
+#[derive(Debug)]
+struct PNode<K, V> {
+    key: K,
+    value: V,
+    left: PLink<K, V>,
+    right: PLink<K, V>,
+    /// depth of the subtree rooted at this node
+    height: usize,
+    /// number of nodes in the subtree rooted at this node
+    size: usize,
+}
+
+/// cloning only shares the root
+impl<K, V> Clone for PersistentBTree<K, V> {
+    fn clone(&self) -> Self {
+        PersistentBTree {
+            root: self.root.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for PersistentBTree<K, V> {
+    fn default() -> Self {
+        PersistentBTree::new()
+    }
+}
+
+impl<K, V> PersistentBTree<K, V> {
+    /// new empty tree
+    pub fn new() -> PersistentBTree<K, V> {
+        PersistentBTree { root: None }
+    }
+
+    /// is the tree empty
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// number of entries
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    /// depth of the tree
+    pub fn depth(&self) -> usize {
+        height(&self.root)
+    }
+
+    /// in-order iterator
+    pub fn iter(&self) -> PersistentIter<'_, K, V> {
+        let mut iter = PersistentIter {
+            stack: vec![],
+            remaining: self.len(),
+        };
+        iter.push_left(&self.root);
+        iter
+    }
+
+    /// do both versions share the same root, so hold the same entries
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.root, &other.root) {
+            (None, None) => true,
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl<K: Eq + PartialOrd + Clone, V: Clone> PersistentBTree<K, V> {
+    /// new version with the given key set to the value
+    pub fn insert(&self, key: K, value: V) -> Self {
+        PersistentBTree {
+            root: Some(insert_into_node(&self.root, key, value)),
+        }
+    }
+
+    /// new version without the given key, sharing everything if the key is absent
+    pub fn delete(&self, key: &K) -> Self {
+        match delete_node(&self.root, key) {
+            Some(root) => PersistentBTree { root },
+            None => self.clone(),
+        }
+    }
+
+    /// the value for the given key
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let mut on = &self.root;
+        while let Some(node) = on {
+            if node.key == *key {
+                return Some(&node.value);
+            }
+            on = if *key < node.key {
+                &node.left
+            } else {
+                &node.right
+            };
+        }
+        None
+    }
+}
+
+/// convert from an iterator
+impl<K: Eq + PartialOrd + Clone, V: Clone> FromIterator<(K, V)> for PersistentBTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut root = None;
+        for (k, v) in iter {
+            root = Some(insert_into_node(&root, k, v));
+        }
+        PersistentBTree { root }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a PersistentBTree<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = PersistentIter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// In-order iterator over a version of the tree
+pub struct PersistentIter<'a, K, V> {
+    stack: Vec<&'a PNode<K, V>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> PersistentIter<'a, K, V> {
+    /// push the node and its left descendants
+    fn push_left(&mut self, mut onode: &'a PLink<K, V>) {
+        while let Some(node) = onode {
+            self.stack.push(node);
+            onode = &node.left;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for PersistentIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left(&node.right);
+        self.remaining -= 1;
+        Some((&node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for PersistentIter<'_, K, V> {}
+
+/// depth of a subtree
+fn height<K, V>(onode: &PLink<K, V>) -> usize {
+    onode.as_ref().map_or(0, |n| n.height)
+}
+
+/// number of nodes in a subtree
+fn size<K, V>(onode: &PLink<K, V>) -> usize {
+    onode.as_ref().map_or(0, |n| n.size)
+}
+
+/// new node over the given subtrees
+fn node<K, V>(key: K, value: V, left: PLink<K, V>, right: PLink<K, V>) -> Rc<PNode<K, V>> {
+    Rc::new(PNode {
+        height: 1 + max(height(&left), height(&right)),
+        size: 1 + size(&left) + size(&right),
+        key,
+        value,
+        left,
+        right,
+    })
+}
+
+/// new node over subtrees whose heights differ by at most 2, rotating to restore the balance
+fn join<K: Clone, V: Clone>(
+    key: K,
+    value: V,
+    left: PLink<K, V>,
+    right: PLink<K, V>,
+) -> Rc<PNode<K, V>> {
+    let (hl, hr) = (height(&left), height(&right));
+    if hl > hr + 1 {
+        let l = left.expect("left subtree higher than the right one");
+        if height(&l.left) >= height(&l.right) {
+            let r = node(key, value, l.right.clone(), right);
+            node(l.key.clone(), l.value.clone(), l.left.clone(), Some(r))
+        } else {
+            let lr = l.right.as_ref().expect("left-right subtree is the highest");
+            let nl = node(
+                l.key.clone(),
+                l.value.clone(),
+                l.left.clone(),
+                lr.left.clone(),
+            );
+            let nr = node(key, value, lr.right.clone(), right);
+            node(lr.key.clone(), lr.value.clone(), Some(nl), Some(nr))
+        }
+    } else if hr > hl + 1 {
+        let r = right.expect("right subtree higher than the left one");
+        if height(&r.right) >= height(&r.left) {
+            let l = node(key, value, left, r.left.clone());
+            node(r.key.clone(), r.value.clone(), Some(l), r.right.clone())
+        } else {
+            let rl = r.left.as_ref().expect("right-left subtree is the highest");
+            let nl = node(key, value, left, rl.left.clone());
+            let nr = node(
+                r.key.clone(),
+                r.value.clone(),
+                rl.right.clone(),
+                r.right.clone(),
+            );
+            node(rl.key.clone(), rl.value.clone(), Some(nl), Some(nr))
+        }
+    } else {
+        node(key, value, left, right)
+    }
+}
+
+/// copy of the subtree with the key set, copying only the path to it
+fn insert_into_node<K: Eq + PartialOrd + Clone, V: Clone>(
+    onode: &PLink<K, V>,
+    key: K,
+    value: V,
+) -> Rc<PNode<K, V>> {
+    match onode {
+        None => node(key, value, None, None),
+        Some(n) => {
+            if n.key == key {
+                node(key, value, n.left.clone(), n.right.clone())
+            } else if key < n.key {
+                let left = insert_into_node(&n.left, key, value);
+                join(n.key.clone(), n.value.clone(), Some(left), n.right.clone())
+            } else {
+                let right = insert_into_node(&n.right, key, value);
+                join(n.key.clone(), n.value.clone(), n.left.clone(), Some(right))
+            }
+        }
+    }
+}
+
+/// copy of the subtree without the key, or None if the key is absent
+fn delete_node<K: Eq + PartialOrd + Clone, V: Clone>(
+    onode: &PLink<K, V>,
+    key: &K,
+) -> Option<PLink<K, V>> {
+    let n = onode.as_ref()?;
+    let nn = if n.key == *key {
+        match (&n.left, &n.right) {
+            (None, r) => return Some(r.clone()),
+            (l, None) => return Some(l.clone()),
+            (l, Some(r)) => {
+                let (rest, next) = remove_min(r);
+                join(next.key.clone(), next.value.clone(), l.clone(), rest)
+            }
+        }
+    } else if *key < n.key {
+        let left = delete_node(&n.left, key)?;
+        join(n.key.clone(), n.value.clone(), left, n.right.clone())
+    } else {
+        let right = delete_node(&n.right, key)?;
+        join(n.key.clone(), n.value.clone(), n.left.clone(), right)
+    };
+    Some(Some(nn))
+}
+
+/// copy of the subtree without its minimum node, and that node
+fn remove_min<K: Clone, V: Clone>(n: &Rc<PNode<K, V>>) -> (PLink<K, V>, &PNode<K, V>) {
+    match &n.left {
+        None => (n.right.clone(), n),
+        Some(l) => {
+            let (rest, min) = remove_min(l);
+            let nn = join(n.key.clone(), n.value.clone(), rest, n.right.clone());
+            (Some(nn), min)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// check the order, balance, heights and sizes of the subtree, returning its keys
+    fn check(onode: &PLink<i32, i32>, keys: &mut Vec<i32>) {
+        if let Some(n) = onode {
+            check(&n.left, keys);
+            assert!(keys.last().is_none_or(|k| *k < n.key));
+            keys.push(n.key);
+            check(&n.right, keys);
+            assert!((height(&n.left) as isize - height(&n.right) as isize).abs() <= 1);
+            assert_eq!(1 + max(height(&n.left), height(&n.right)), n.height);
+            assert_eq!(1 + size(&n.left) + size(&n.right), n.size);
+        }
+    }
+
+    /// number of nodes of the subtree that are not shared with the other version
+    fn unshared(onode: &PLink<i32, i32>, other: &PersistentBTree<i32, i32>) -> usize {
+        fn nodes<'a>(onode: &'a PLink<i32, i32>, acc: &mut Vec<&'a Rc<PNode<i32, i32>>>) {
+            if let Some(n) = onode {
+                acc.push(n);
+                nodes(&n.left, acc);
+                nodes(&n.right, acc);
+            }
+        }
+        let mut mine = vec![];
+        nodes(onode, &mut mine);
+        let mut theirs = vec![];
+        nodes(&other.root, &mut theirs);
+        mine.iter()
+            .filter(|n| !theirs.iter().any(|o| Rc::ptr_eq(n, o)))
+            .count()
+    }
+
+    #[test]
+    fn test_versions() {
+        let v0 = PersistentBTree::new();
+        let v1 = v0.insert(1, 10);
+        let v2 = v1.insert(2, 20);
+        let v3 = v2.insert(1, 11);
+        let v4 = v3.delete(&2);
+        assert!(v0.is_empty());
+        assert_eq!(None, v0.get(&1));
+        assert_eq!(Some(&10), v1.get(&1));
+        assert_eq!(None, v1.get(&2));
+        assert_eq!(vec![(&1, &10), (&2, &20)], v2.iter().collect::<Vec<_>>());
+        assert_eq!(vec![(&1, &11), (&2, &20)], v3.iter().collect::<Vec<_>>());
+        assert_eq!(vec![(&1, &11)], v4.iter().collect::<Vec<_>>());
+        assert_eq!((2, 1), (v3.len(), v4.len()));
+
+        let copy = v3.clone();
+        assert!(copy.ptr_eq(&v3));
+        assert!(v3.delete(&7).ptr_eq(&v3));
+        assert!(!v3.delete(&1).ptr_eq(&v3));
+    }
+
+    #[test]
+    fn test_sharing() {
+        let tree = (0..1000).map(|i| (i, i)).collect::<PersistentBTree<_, _>>();
+        let mut keys = vec![];
+        check(&tree.root, &mut keys);
+        assert_eq!((0..1000).collect::<Vec<_>>(), keys);
+        assert_eq!(10, tree.depth());
+
+        // only the path to the key is copied
+        let updated = tree.insert(500, -1);
+        assert!(unshared(&updated.root, &tree) <= tree.depth());
+        let removed = tree.delete(&500);
+        assert!(unshared(&removed.root, &tree) <= tree.depth() + 1);
+        assert_eq!(Some(&500), tree.get(&500));
+        assert_eq!(Some(&-1), updated.get(&500));
+        assert_eq!(None, removed.get(&500));
+        assert_eq!(1000, tree.len());
+        assert_eq!(999, removed.len());
+    }
+
+    #[test]
+    fn test_history() {
+        // every version stays equal to the map it was built alongside
+        let mut versions = vec![(PersistentBTree::new(), BTreeMap::new())];
+        for i in 0..2000 {
+            let (tree, map) = versions.last().unwrap();
+            let k = i * 7919 % 500;
+            let (mut tree, mut map) = (tree.clone(), map.clone());
+            if i % 3 == 2 {
+                tree = tree.delete(&k);
+                map.remove(&k);
+            } else {
+                tree = tree.insert(k, i);
+                map.insert(k, i);
+            }
+            versions.push((tree, map));
+        }
+        for (tree, map) in &versions {
+            let mut keys = vec![];
+            check(&tree.root, &mut keys);
+            assert_eq!(map.len(), tree.len());
+            assert_eq!(
+                map.iter().collect::<Vec<_>>(),
+                tree.iter().collect::<Vec<_>>()
+            );
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Render trees as Graphviz DOT or as indented text, to look at their shape
+use super::{BNode, BTree, TreeNode};
+use std::cell::RefCell;
+use std::fmt::{Display, Write};
+use std::rc::Rc;
+
+/// The characters used to draw the branches of a text tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeChars {
+    /// `|--` and `` `-- ``
+    Ascii,
+    /// `├──` and `└──`
+    Unicode,
+}
+
+impl TreeChars {
+    /// prefixes for a child that has siblings after it, for the last child,
+    /// and below them while the parent has more children or not
+    fn branches(self) -> [&'static str; 4] {
+        match self {
+            TreeChars::Ascii => ["|-- ", "`-- ", "|   ", "    "],
+            TreeChars::Unicode => ["├── ", "└── ", "│   ", "    "],
+        }
+    }
+}
+
+impl<K: Display, V: Display> BTree<K, V> {
+    /// Graphviz DOT graph of the tree, with the depth, height and balance of each node
+    pub fn to_dot(&self) -> String {
+        dot(&visit(self.root.as_deref(), bnode_children, bnode_label))
+    }
+
+    /// indented text tree, one node per line with its depth, height and balance
+    pub fn to_text(&self, chars: TreeChars) -> String {
+        text(
+            &visit(self.root.as_deref(), bnode_children, bnode_label),
+            chars,
+        )
+    }
+}
+
+/// Graphviz DOT graph of a LeetCode tree, with the depth of each node
+pub fn tree_node_to_dot(root: &Option<Rc<RefCell<TreeNode>>>) -> String {
+    dot(&visit(root.clone(), tree_node_children, tree_node_label))
+}
+
+/// indented text of a LeetCode tree, one node per line with its depth
+pub fn tree_node_to_text(root: &Option<Rc<RefCell<TreeNode>>>, chars: TreeChars) -> String {
+    text(
+        &visit(root.clone(), tree_node_children, tree_node_label),
+        chars,
+    )
+}
+
+fn bnode_children<'a, K, V>(node: &&'a BNode<K, V>) -> [Option<&'a BNode<K, V>>; 2] {
+    [node.left.as_deref(), node.right.as_deref()]
+}
+
+fn bnode_label<K: Display, V: Display>(node: &&BNode<K, V>, depth: usize) -> String {
+    format!(
+        "{} = {} (depth {}, height {}, balance {:+})",
+        node.key,
+        node.value,
+        depth,
+        node.height,
+        node.balance()
+    )
+}
+
+fn tree_node_children(node: &Rc<RefCell<TreeNode>>) -> [Option<Rc<RefCell<TreeNode>>>; 2] {
+    let n = node.borrow();
+    [n.left.clone(), n.right.clone()]
+}
+
+fn tree_node_label(node: &Rc<RefCell<TreeNode>>, depth: usize) -> String {
+    format!("{} (depth {})", node.borrow().val, depth)
+}
+
+/// A node met by the pre-order walk
+struct Visited {
+    label: String,
+    depth: usize,
+    /// is it the right child of its parent
+    right: bool,
+    /// is it the last child of its parent
+    last: bool,
+    /// index of the left and right children
+    children: [Option<usize>; 2],
+}
+
+/// walk the tree in pre-order with an explicit stack, so deep trees don't overflow
+fn visit<N>(
+    root: Option<N>,
+    children: impl Fn(&N) -> [Option<N>; 2],
+    label: impl Fn(&N, usize) -> String,
+) -> Vec<Visited> {
+    let mut visited: Vec<Visited> = vec![];
+    // node, parent index, is right child, is last child
+    let mut stack: Vec<(N, Option<usize>, bool, bool)> = vec![];
+    stack.extend(root.map(|n| (n, None, false, true)));
+    while let Some((node, parent, right, last)) = stack.pop() {
+        let id = visited.len();
+        let depth = match parent {
+            Some(p) => {
+                visited[p].children[right as usize] = Some(id);
+                visited[p].depth + 1
+            }
+            None => 0,
+        };
+        visited.push(Visited {
+            label: label(&node, depth),
+            depth,
+            right,
+            last,
+            children: [None, None],
+        });
+        let [l, r] = children(&node);
+        let has_right = r.is_some();
+        stack.extend(r.map(|n| (n, Some(id), true, true)));
+        stack.extend(l.map(|n| (n, Some(id), false, !has_right)));
+    }
+    visited
+}
+
+/// quote a label for DOT
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// DOT graph, with an empty point standing for a missing child so the sides stay apparent
+fn dot(visited: &[Visited]) -> String {
+    let mut out = String::from("digraph {\n");
+    for (id, v) in visited.iter().enumerate() {
+        let _ = writeln!(out, "    n{} [label=\"{}\"];", id, escape(&v.label));
+        if v.children == [None, None] {
+            continue;
+        }
+        for (side, child) in v.children.iter().enumerate() {
+            match child {
+                Some(c) => {
+                    let _ = writeln!(out, "    n{} -> n{};", id, c);
+                }
+                None => {
+                    let _ = writeln!(out, "    n{}_{} [shape=point];", id, side);
+                    let _ = writeln!(out, "    n{} -> n{}_{};", id, id, side);
+                }
+            }
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// indented text, each child prefixed by L or R
+fn text(visited: &[Visited], chars: TreeChars) -> String {
+    let [branch, last_branch, under, last_under] = chars.branches();
+    let mut out = String::new();
+    // for each ancestor below the root, is it the last child
+    let mut lasts: Vec<bool> = vec![];
+    for v in visited {
+        if v.depth > 0 {
+            lasts.truncate(v.depth - 1);
+            for &l in &lasts {
+                out.push_str(if l { last_under } else { under });
+            }
+            out.push_str(if v.last { last_branch } else { branch });
+            out.push_str(if v.right { "R " } else { "L " });
+            lasts.push(v.last);
+        }
+        out.push_str(&v.label);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_btree() {
+        let mut tree = BTree::new();
+        for k in [8, 3, 1, 6, 4, 10] {
+            tree.insert(k, k * 10);
+        }
+        assert_eq!(
+            "\
+8 = 80 (depth 0, height 4, balance +2)
+├── L 3 = 30 (depth 1, height 3, balance -1)
+│   ├── L 1 = 10 (depth 2, height 1, balance +0)
+│   └── R 6 = 60 (depth 2, height 2, balance +1)
+│       └── L 4 = 40 (depth 3, height 1, balance +0)
+└── R 10 = 100 (depth 1, height 1, balance +0)
+",
+            tree.to_text(TreeChars::Unicode)
+        );
+        assert_eq!(
+            "\
+8 = 80 (depth 0, height 4, balance +2)
+|-- L 3 = 30 (depth 1, height 3, balance -1)
+|   |-- L 1 = 10 (depth 2, height 1, balance +0)
+|   `-- R 6 = 60 (depth 2, height 2, balance +1)
+|       `-- L 4 = 40 (depth 3, height 1, balance +0)
+`-- R 10 = 100 (depth 1, height 1, balance +0)
+",
+            tree.to_text(TreeChars::Ascii)
+        );
+        let dot = tree.to_dot();
+        assert!(dot.starts_with(
+            "digraph {\n    n0 [label=\"8 = 80 (depth 0, height 4, balance +2)\"];\n"
+        ));
+        assert!(dot.contains("    n0 -> n1;\n    n0 -> n5;\n"));
+        // 6 has no right child
+        assert!(dot.contains("    n3 -> n4;\n    n3_1 [shape=point];\n    n3 -> n3_1;\n"));
+        assert!(dot.ends_with("}\n"));
+
+        let empty: BTree<i32, i32> = BTree::new();
+        assert_eq!("", empty.to_text(TreeChars::Ascii));
+        assert_eq!("digraph {\n}\n", empty.to_dot());
+        let quoted = vec![(1, "a \"b\"")].into_iter().collect::<BTree<_, _>>();
+        assert!(quoted.to_dot().contains("label=\"1 = a \\\"b\\\" (depth 0"));
+    }
+
+    #[test]
+    fn test_tree_node() {
+        let root = TreeNode::parse("[1,null,2,3]").unwrap();
+        assert_eq!(
+            "1 (depth 0)\n`-- R 2 (depth 1)\n    `-- L 3 (depth 2)\n",
+            tree_node_to_text(&root, TreeChars::Ascii)
+        );
+        assert_eq!(
+            "\
+digraph {
+    n0 [label=\"1 (depth 0)\"];
+    n0_0 [shape=point];
+    n0 -> n0_0;
+    n0 -> n1;
+    n1 [label=\"2 (depth 1)\"];
+    n1 -> n2;
+    n1_1 [shape=point];
+    n1 -> n1_1;
+    n2 [label=\"3 (depth 2)\"];
+}
+",
+            tree_node_to_dot(&root)
+        );
+    }
+}
@@ -1,82 +1,802 @@
 //! Simple calculator parser and evaluator
 //! Submitted to http://www.rosettacode.org/wiki/Arithmetic_evaluation#Rust
 
+use std::collections::HashMap;
+use std::error::Error;
+use std::f64::consts;
+use std::fmt;
+use std::rc::Rc;
+
+pub mod lexer;
+pub mod number;
+pub mod units;
+pub mod value;
+pub mod vm;
+
+pub use self::lexer::*;
+pub use self::number::*;
+pub use self::units::*;
+pub use self::value::*;
+pub use self::vm::*;
+
 /// Binary operator
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Operator {
     Add,
     Substract,
     Multiply,
     Divide,
+    Modulo,
+    Power,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+/// How operators of the same precedence group together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    /// `a-b-c` is `(a-b)-c`
+    Left,
+    /// `a^b^c` is `a^(b^c)`
+    Right,
+}
+
+/// The operator table: symbol, operator, precedence and associativity.
+/// Higher precedence binds tighter; longer symbols must come before their prefixes.
+const OPERATORS: &[(&str, Operator, usize, Associativity)] = &[
+    ("||", Operator::Or, 1, Associativity::Left),
+    ("&&", Operator::And, 2, Associativity::Left),
+    ("==", Operator::Equal, 3, Associativity::Left),
+    ("!=", Operator::NotEqual, 3, Associativity::Left),
+    ("<<", Operator::ShiftLeft, 7, Associativity::Left),
+    (">>", Operator::ShiftRight, 7, Associativity::Left),
+    ("<=", Operator::LessEqual, 3, Associativity::Left),
+    (">=", Operator::GreaterEqual, 3, Associativity::Left),
+    ("<", Operator::Less, 3, Associativity::Left),
+    (">", Operator::Greater, 3, Associativity::Left),
+    ("|", Operator::BitOr, 4, Associativity::Left),
+    ("^^", Operator::BitXor, 5, Associativity::Left),
+    ("&", Operator::BitAnd, 6, Associativity::Left),
+    ("+", Operator::Add, 8, Associativity::Left),
+    ("-", Operator::Substract, 8, Associativity::Left),
+    ("*", Operator::Multiply, 9, Associativity::Left),
+    ("/", Operator::Divide, 9, Associativity::Left),
+    ("%", Operator::Modulo, 9, Associativity::Left),
+    ("^", Operator::Power, 11, Associativity::Right),
+];
+
+/// Precedence of the unary operators: tighter than multiplication, looser than power, so `-2^2` is `-(2^2)`
+const UNARY_PRECEDENCE: usize = 10;
+
+/// Default maximum number of nested calls to user defined functions
+pub const DEFAULT_MAX_DEPTH: usize = 100;
+
+impl Operator {
+    /// the entry for this operator in the operator table
+    fn entry(self) -> &'static (&'static str, Operator, usize, Associativity) {
+        OPERATORS
+            .iter()
+            .find(|e| e.1 == self)
+            .expect("operator missing from the operator table")
+    }
+
+    /// the textual symbol
+    pub fn symbol(self) -> &'static str {
+        self.entry().0
+    }
+
+    /// a precedence rank, higher binds tighter
+    pub fn precedence(self) -> usize {
+        self.entry().2
+    }
+
+    /// the associativity
+    pub fn associativity(self) -> Associativity {
+        self.entry().3
+    }
+
+    /// does the operator take and return numbers
+    pub fn is_arithmetic(self) -> bool {
+        matches!(
+            self,
+            Operator::Add
+                | Operator::Substract
+                | Operator::Multiply
+                | Operator::Divide
+                | Operator::Modulo
+                | Operator::Power
+        ) || self.is_bitwise()
+    }
+
+    /// does the operator compare two values
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            Operator::Equal
+                | Operator::NotEqual
+                | Operator::Less
+                | Operator::LessEqual
+                | Operator::Greater
+                | Operator::GreaterEqual
+        )
+    }
+
+    /// does the operator only accept integer valued operands
+    pub fn is_bitwise(self) -> bool {
+        matches!(
+            self,
+            Operator::BitAnd
+                | Operator::BitOr
+                | Operator::BitXor
+                | Operator::ShiftLeft
+                | Operator::ShiftRight
+        )
+    }
+
+    /// apply a floating point operator to two values
+    /// panics on comparison, boolean and bitwise operators, see `Value::binary` and `Number::binary`
+    pub fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            Operator::Add => a + b,
+            Operator::Substract => a - b,
+            Operator::Multiply => a * b,
+            Operator::Divide => a / b,
+            Operator::Modulo => a % b,
+            Operator::Power => a.powf(b),
+            _ => unreachable!("'{}' is not a floating point operator", self.symbol()),
+        }
+    }
+}
+
+/// Unary operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+impl UnaryOperator {
+    /// the textual symbol
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOperator::Negate => "-",
+            UnaryOperator::Not => "!",
+        }
+    }
+
+    /// apply an arithmetic operator to a value
+    /// panics on `!`, see `Value::unary`
+    pub fn apply(self, a: f64) -> f64 {
+        match self {
+            UnaryOperator::Negate => -a,
+            UnaryOperator::Not => unreachable!("'!' is not arithmetic"),
+        }
+    }
 }
 
 /// A node in the tree
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Node {
     Value(f64),
+    /// a number literal the float does not hold exactly, like `9007199254740993`,
+    /// with its source text so the exact number types can still read it
+    Literal(f64, String),
+    Variable(String),
+    Call(String, Vec<Node>),
     SubNode(Box<Node>),
+    Unary(UnaryOperator, Box<Node>),
     Binary(Operator, Box<Node>, Box<Node>),
+    /// `if(condition, then, else)`, only evaluating the chosen branch
+    If(Box<Node>, Box<Node>, Box<Node>),
+    /// a number followed by a unit, like `3 m s^-1`
+    Quantity(f64, Unit),
+    /// conversion of a quantity to a unit, like `10 km to mi`
+    Convert(Box<Node>, Unit),
 }
 
-/// parse a string into a node
-pub fn parse(txt: &str) -> Option<Node> {
-    let chars = txt.chars().filter(|c| *c != ' ').collect();
-    parse_expression(&chars, 0).map(|(_, n)| n)
-}
-
-/// parse an expression into a node, keeping track of the position in the character vector
-fn parse_expression(chars: &Vec<char>, pos: usize) -> Option<(usize, Node)> {
-    match parse_start(chars, pos) {
-        Some((new_pos, first)) => match parse_operator(chars, new_pos) {
-            Some((new_pos2, op)) => {
-                if let Some((new_pos3, second)) = parse_expression(chars, new_pos2) {
-                    Some((new_pos3, combine(op, first, second)))
-                } else {
-                    None
+impl Node {
+    /// the precedence of the node when displayed, atoms binding tightest
+    fn precedence(&self) -> usize {
+        match self {
+            Node::SubNode(n) => n.precedence(),
+            Node::Binary(op, _, _) => op.precedence(),
+            Node::Convert(_, _) => 0,
+            // `(3 m) ^ 2` is not `3 m^2`
+            Node::Quantity(_, _) => UNARY_PRECEDENCE,
+            _ if self.is_prefix() => UNARY_PRECEDENCE,
+            _ => usize::MAX,
+        }
+    }
+
+    /// does the node display starting with a unary operator
+    fn is_prefix(&self) -> bool {
+        match self {
+            Node::SubNode(n) => n.is_prefix(),
+            Node::Value(v) => v.is_sign_negative(),
+            Node::Unary(_, _) => true,
+            _ => false,
+        }
+    }
+}
+
+/// write an operand, wrapped in parentheses if needed
+fn fmt_operand(f: &mut fmt::Formatter<'_>, node: &Node, parenthesis: bool) -> fmt::Result {
+    if parenthesis {
+        write!(f, "({})", node)
+    } else {
+        write!(f, "{}", node)
+    }
+}
+
+/// Display the node with the minimal amount of parentheses, so that it parses back to the same tree
+/// (minus the redundant sub nodes)
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Value(v) => write!(f, "{}", v),
+            Node::Literal(_, txt) => write!(f, "{}", txt),
+            Node::Variable(name) => write!(f, "{}", name),
+            Node::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                for (ix, arg) in args.iter().enumerate() {
+                    if ix > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
                 }
+                write!(f, ")")
             }
-            None => Some((new_pos, first)),
-        },
-        None => None,
+            Node::If(c, n1, n2) => write!(f, "if({}, {}, {})", c, n1, n2),
+            Node::Quantity(v, unit) => write!(f, "{} {}", v, unit),
+            Node::Convert(n, unit) => write!(f, "{} to {}", n, unit),
+            Node::SubNode(n) => n.fmt(f),
+            Node::Unary(op, n) => {
+                write!(f, "{}", op.symbol())?;
+                fmt_operand(f, n, n.precedence() < UNARY_PRECEDENCE)
+            }
+            Node::Binary(op, n1, n2) => {
+                let (min1, min2) = match op.associativity() {
+                    Associativity::Left => (op.precedence(), op.precedence() + 1),
+                    Associativity::Right => (op.precedence() + 1, op.precedence()),
+                };
+                fmt_operand(f, n1, n1.precedence() < min1)?;
+                write!(f, " {} ", op.symbol())?;
+                // a unary operator can always start the right operand
+                fmt_operand(f, n2, n2.precedence() < min2 && !n2.is_prefix())
+            }
+        }
+    }
+}
+
+/// The kind of input the parser was expecting when it failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    /// a number, a variable or an opening parenthesis
+    Value,
+    /// a closing parenthesis
+    CloseParenthesis,
+    /// a comma or a closing parenthesis, after a function argument
+    Separator,
+    /// a comma, between the parts of a conditional
+    Comma,
+    /// a unit name, after a number or `to`
+    Unit,
+    /// an integer, as the exponent of a unit
+    Exponent,
+    /// a parameter name, in a function definition
+    Parameter,
+    /// `=`, after the parameters of a function definition
+    Assign,
+    /// an operator or the end of the input
+    End,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Value => write!(f, "a value"),
+            Expected::CloseParenthesis => write!(f, "')'"),
+            Expected::Separator => write!(f, "',' or ')'"),
+            Expected::Comma => write!(f, "','"),
+            Expected::Unit => write!(f, "a unit"),
+            Expected::Exponent => write!(f, "an integer exponent"),
+            Expected::Parameter => write!(f, "a parameter name"),
+            Expected::Assign => write!(f, "'='"),
+            Expected::End => write!(f, "an operator or the end of input"),
+        }
+    }
+}
+
+/// Error returned when a string cannot be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// character offset of the error in the input
+    pub pos: usize,
+    /// what the parser was expecting at that position
+    pub expected: Expected,
+    /// the offending input, or None if the end of input was reached
+    pub found: Option<String>,
+}
+
+impl ParseError {
+    /// new error at the token at the given position
+    fn new(tokens: &[Token], pos: usize, expected: Expected) -> ParseError {
+        let token = &tokens[pos];
+        ParseError {
+            pos: token.span.start,
+            expected,
+            found: match token.kind {
+                TokenKind::End => None,
+                _ => Some(token.text.clone()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.found {
+            Some(s) => write!(
+                f,
+                "expected {} at position {}, found '{}'",
+                self.expected, self.pos, s
+            ),
+            None => write!(
+                f,
+                "expected {} at position {}, found end of input",
+                self.expected, self.pos
+            ),
+        }
     }
 }
 
-/// combine nodes to respect associativity rules
-fn combine(op: Operator, first: Node, second: Node) -> Node {
-    match second {
-        Node::Binary(op2, v21, v22) => {
-            if precedence(&op) >= precedence(&op2) {
-                Node::Binary(op2, Box::new(combine(op, first, *v21)), v22)
-            } else {
-                Node::Binary(op, Box::new(first), Box::new(Node::Binary(op2, v21, v22)))
+impl Error for ParseError {}
+
+/// Error returned when a node cannot be evaluated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    /// a variable has no value in the environment
+    UnboundVariable(String),
+    /// a function is not defined in the environment
+    UnknownFunction(String),
+    /// a function was called with a number of arguments it does not accept
+    WrongArity { name: String, found: usize },
+    /// no derivative is known for a function or operator
+    NotDifferentiable(String),
+    /// the result does not fit in the number type
+    Overflow,
+    /// division by zero with a number type that cannot represent infinity
+    DivisionByZero,
+    /// the value cannot be represented exactly in the number type
+    NotRepresentable(String),
+    /// an operator or a condition was given a value of the wrong type
+    TypeMismatch {
+        expected: ValueType,
+        found: ValueType,
+    },
+    /// a bitwise operator was given a number that is not an integer
+    NotInteger(String),
+    /// quantities of different dimensions were added, compared or converted
+    IncompatibleUnits(Dimension, Dimension),
+    /// user defined functions were nested deeper than the environment allows
+    RecursionLimit { name: String, limit: usize },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::UnboundVariable(name) => write!(f, "unbound variable '{}'", name),
+            EvalError::UnknownFunction(name) => write!(f, "unknown function '{}'", name),
+            EvalError::WrongArity { name, found } => write!(
+                f,
+                "function '{}' cannot be called with {} argument(s)",
+                name, found
+            ),
+            EvalError::NotDifferentiable(name) => write!(f, "cannot differentiate '{}'", name),
+            EvalError::Overflow => write!(f, "numeric overflow"),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::NotRepresentable(v) => write!(f, "{} cannot be represented exactly", v),
+            EvalError::NotInteger(v) => write!(f, "bitwise operators need integers, found {}", v),
+            EvalError::IncompatibleUnits(d1, d2) => {
+                write!(f, "incompatible units {} and {}", d1, d2)
+            }
+            EvalError::TypeMismatch { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
             }
+            EvalError::RecursionLimit { name, limit } => write!(
+                f,
+                "function '{}' exceeded the limit of {} nested calls",
+                name, limit
+            ),
         }
-        _ => Node::Binary(op, Box::new(first), Box::new(second)),
     }
 }
 
-/// a precedence rank for operators
-fn precedence(op: &Operator) -> usize {
-    match op {
-        Operator::Multiply | Operator::Divide => 2,
-        _ => 1,
+impl Error for EvalError {}
+
+/// Error returned when evaluating a string, either while parsing or while evaluating
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CalcError {
+    Parse(ParseError),
+    Eval(EvalError),
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcError::Parse(e) => e.fmt(f),
+            CalcError::Eval(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for CalcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CalcError::Parse(e) => Some(e),
+            CalcError::Eval(e) => Some(e),
+        }
+    }
+}
+
+impl From<ParseError> for CalcError {
+    fn from(e: ParseError) -> Self {
+        CalcError::Parse(e)
+    }
+}
+
+impl From<EvalError> for CalcError {
+    fn from(e: EvalError) -> Self {
+        CalcError::Eval(e)
+    }
+}
+
+/// The Rust implementation of a function
+type FunctionBody = Rc<dyn Fn(&[f64]) -> f64>;
+
+/// A function that can be called from an expression
+#[derive(Clone)]
+pub struct Function {
+    /// number of arguments, or None if the function takes one or more arguments
+    arity: Option<usize>,
+    body: FunctionBody,
+}
+
+impl Function {
+    /// check the function accepts the given number of arguments
+    pub fn check_arity(&self, name: &str, count: usize) -> Result<(), EvalError> {
+        match self.arity {
+            Some(n) if n != count => Err(EvalError::WrongArity {
+                name: name.to_string(),
+                found: count,
+            }),
+            None if count == 0 => Err(EvalError::WrongArity {
+                name: name.to_string(),
+                found: 0,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// call the function, checking the number of arguments
+    pub fn call(&self, name: &str, args: &[f64]) -> Result<f64, EvalError> {
+        self.check_arity(name, args.len())?;
+        Ok((self.body)(args))
     }
 }
 
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+/// A function defined in the calculator language, like `f(x, y) = x^2 + y`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    name: String,
+    params: Vec<String>,
+    body: Node,
+}
+
+impl Definition {
+    /// new definition of the named function
+    pub fn new(name: &str, params: Vec<String>, body: Node) -> Definition {
+        Definition {
+            name: name.to_string(),
+            params,
+            body,
+        }
+    }
+
+    /// the function name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// the parameter names
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    /// the body, evaluated with the parameters bound to the arguments
+    pub fn body(&self) -> &Node {
+        &self.body
+    }
+}
+
+impl fmt::Display for Definition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}({}) = {}",
+            self.name,
+            self.params.join(", "),
+            self.body
+        )
+    }
+}
+
+/// The variables and functions available in an evaluation
+#[derive(Debug, Clone)]
+pub struct Environment {
+    variables: HashMap<String, Value>,
+    functions: HashMap<String, Function>,
+    definitions: HashMap<String, Definition>,
+    max_depth: usize,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::new()
+    }
+}
+
+impl Environment {
+    /// new environment containing the built-in functions and the pi, e, true and false constants
+    pub fn new() -> Environment {
+        let mut env = Environment::empty();
+        env.set("pi", consts::PI);
+        env.set("e", consts::E);
+        env.set("true", true);
+        env.set("false", false);
+        env.define_function("sin", 1, |a| a[0].sin());
+        env.define_function("cos", 1, |a| a[0].cos());
+        env.define_function("tan", 1, |a| a[0].tan());
+        env.define_function("sqrt", 1, |a| a[0].sqrt());
+        env.define_function("ln", 1, |a| a[0].ln());
+        env.define_function("exp", 1, |a| a[0].exp());
+        env.define_function("abs", 1, |a| a[0].abs());
+        env.define_function("round", 1, |a| a[0].round());
+        env.define_variadic("min", |a| a.iter().copied().fold(f64::INFINITY, f64::min));
+        env.define_variadic("max", |a| {
+            a.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+        });
+        env
+    }
+
+    /// new environment without any variable or function
+    pub fn empty() -> Environment {
+        Environment {
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            definitions: HashMap::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    /// define a function taking a fixed number of arguments
+    pub fn define_function<F>(&mut self, name: &str, arity: usize, f: F)
+    where
+        F: Fn(&[f64]) -> f64 + 'static,
+    {
+        self.definitions.remove(name);
+        self.functions.insert(
+            name.to_string(),
+            Function {
+                arity: Some(arity),
+                body: Rc::new(f),
+            },
+        );
+    }
+
+    /// define a function taking one or more arguments
+    pub fn define_variadic<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(&[f64]) -> f64 + 'static,
+    {
+        self.definitions.remove(name);
+        self.functions.insert(
+            name.to_string(),
+            Function {
+                arity: None,
+                body: Rc::new(f),
+            },
+        );
+    }
+
+    /// get a function, if defined
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    /// add a user defined function, replacing any function of the same name.
+    /// Returns the previous definition if any
+    pub fn define(&mut self, definition: Definition) -> Option<Definition> {
+        self.functions.remove(&definition.name);
+        self.definitions.insert(definition.name.clone(), definition)
+    }
+
+    /// get a user defined function, if defined
+    pub fn definition(&self, name: &str) -> Option<&Definition> {
+        self.definitions.get(name)
+    }
+
+    /// the maximum number of nested calls to user defined functions
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// set the maximum number of nested calls to user defined functions
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// bind a variable to a value, returning the previous value if any
+    pub fn set<V: Into<Value>>(&mut self, name: &str, value: V) -> Option<Value> {
+        self.variables.insert(name.to_string(), value.into())
+    }
+
+    /// get the value of a variable, if bound
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.variables.get(name).cloned()
+    }
+}
+
+/// parse a string into a node
+pub fn parse(txt: &str) -> Result<Node, ParseError> {
+    let tokens = tokenize(txt);
+    let (pos, n) = parse_expression(&tokens, 0)?;
+    match tokens[pos].kind {
+        TokenKind::End => Ok(n),
+        _ => Err(ParseError::new(&tokens, pos, Expected::End)),
+    }
+}
+
+/// A line of input: an expression, or the definition of a function
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Expression(Node),
+    Definition(Definition),
+}
+
+/// parse a string into an expression, or a function definition like `f(x, y) = x^2 + y`
+pub fn parse_statement(txt: &str) -> Result<Statement, ParseError> {
+    let tokens = tokenize(txt);
+    let (pos, statement) = match (&tokens[0].kind, tokens.get(1).map(|t| &t.kind)) {
+        (TokenKind::Identifier(name), Some(TokenKind::OpenParenthesis))
+            if tokens.iter().any(|t| t.kind == TokenKind::Assign) =>
+        {
+            let (pos, definition) = parse_definition(&tokens, 2, name)?;
+            (pos, Statement::Definition(definition))
+        }
+        _ => {
+            let (pos, n) = parse_expression(&tokens, 0)?;
+            (pos, Statement::Expression(n))
+        }
+    };
+    match tokens[pos].kind {
+        TokenKind::End => Ok(statement),
+        _ => Err(ParseError::new(&tokens, pos, Expected::End)),
+    }
+}
+
+/// parse the rest of a function definition after the opening parenthesis:
+/// the comma separated parameters, the closing parenthesis, `=` and the body
+fn parse_definition(
+    tokens: &[Token],
+    pos: usize,
+    name: &str,
+) -> Result<(usize, Definition), ParseError> {
+    let mut pos = pos;
+    let mut params = vec![];
+    if tokens[pos].kind == TokenKind::CloseParenthesis {
+        pos += 1;
+    } else {
+        loop {
+            match &tokens[pos].kind {
+                TokenKind::Identifier(param) => params.push(param.clone()),
+                _ => return Err(ParseError::new(tokens, pos, Expected::Parameter)),
+            }
+            match tokens[pos + 1].kind {
+                TokenKind::Comma => pos += 2,
+                TokenKind::CloseParenthesis => {
+                    pos += 2;
+                    break;
+                }
+                _ => return Err(ParseError::new(tokens, pos + 1, Expected::Separator)),
+            }
+        }
+    }
+    if tokens[pos].kind != TokenKind::Assign {
+        return Err(ParseError::new(tokens, pos, Expected::Assign));
+    }
+    let (pos, body) = parse_expression(tokens, pos + 1)?;
+    Ok((pos, Definition::new(name, params, body)))
+}
+
+/// parse an expression into a node, keeping track of the position in the token vector.
+/// Unit conversions with `to` bind looser than any operator.
+fn parse_expression(tokens: &[Token], pos: usize) -> Result<(usize, Node), ParseError> {
+    let (mut pos, mut node) = parse_operation(tokens, pos, 0)?;
+    while matches!(&tokens[pos].kind, TokenKind::Identifier(name) if name == "to") {
+        let (new_pos, unit) = parse_unit(tokens, pos + 1)?;
+        node = Node::Convert(Box::new(node), unit);
+        pos = new_pos;
+    }
+    Ok((pos, node))
+}
+
+/// precedence climbing: parse operands joined by operators of at least the given precedence
+fn parse_operation(
+    tokens: &[Token],
+    pos: usize,
+    min_precedence: usize,
+) -> Result<(usize, Node), ParseError> {
+    let (mut pos, mut first) = parse_unary(tokens, pos)?;
+    while let TokenKind::Operator(op) = tokens[pos].kind {
+        if op.precedence() < min_precedence {
+            break;
+        }
+        let next_precedence = match op.associativity() {
+            Associativity::Left => op.precedence() + 1,
+            Associativity::Right => op.precedence(),
+        };
+        let (new_pos, second) = parse_operation(tokens, pos + 1, next_precedence)?;
+        first = Node::Binary(op, Box::new(first), Box::new(second));
+        pos = new_pos;
+    }
+    Ok((pos, first))
+}
+
+/// parse an operand, optionally preceded by unary operators
+fn parse_unary(tokens: &[Token], pos: usize) -> Result<(usize, Node), ParseError> {
+    let op = match tokens[pos].kind {
+        TokenKind::Operator(Operator::Substract) => UnaryOperator::Negate,
+        TokenKind::Not => UnaryOperator::Not,
+        _ => return parse_start(tokens, pos),
+    };
+    let (new_pos, node) = parse_operation(tokens, pos + 1, UNARY_PRECEDENCE)?;
+    Ok((new_pos, Node::Unary(op, Box::new(node))))
+}
+
 /// try to parse from the start of an expression (either a parenthesis or a value)
-fn parse_start(chars: &Vec<char>, pos: usize) -> Option<(usize, Node)> {
-    match start_parenthesis(chars, pos) {
+fn parse_start(tokens: &[Token], pos: usize) -> Result<(usize, Node), ParseError> {
+    match start_parenthesis(tokens, pos) {
         Some(new_pos) => {
-            let r = parse_expression(chars, new_pos);
-            end_parenthesis(chars, r)
+            let r = parse_expression(tokens, new_pos)?;
+            end_parenthesis(tokens, r)
         }
-        None => parse_value(chars, pos),
+        None => parse_value(tokens, pos),
     }
 }
 
 /// match a starting parentheseis
-fn start_parenthesis(chars: &Vec<char>, pos: usize) -> Option<usize> {
-    if pos < chars.len() && chars[pos] == '(' {
+fn start_parenthesis(tokens: &[Token], pos: usize) -> Option<usize> {
+    if tokens[pos].kind == TokenKind::OpenParenthesis {
         Some(pos + 1)
     } else {
         None
@@ -84,79 +804,548 @@ fn start_parenthesis(chars: &Vec<char>, pos: usize) -> Option<usize> {
 }
 
 /// match an end parenthesis, if successful will create a sub node contained the wrapped expression
-fn end_parenthesis(chars: &Vec<char>, wrapped: Option<(usize, Node)>) -> Option<(usize, Node)> {
-    match wrapped {
-        Some((pos, node)) => {
-            if pos < chars.len() && chars[pos] == ')' {
-                Some((pos + 1, Node::SubNode(Box::new(node))))
-            } else {
-                None
+fn end_parenthesis(tokens: &[Token], wrapped: (usize, Node)) -> Result<(usize, Node), ParseError> {
+    let (pos, node) = wrapped;
+    if tokens[pos].kind == TokenKind::CloseParenthesis {
+        Ok((pos + 1, Node::SubNode(Box::new(node))))
+    } else {
+        Err(ParseError::new(tokens, pos, Expected::CloseParenthesis))
+    }
+}
+
+/// the node for a number literal, keeping its text if the float is not exact
+fn literal(v: f64, txt: &str) -> Node {
+    if Rational::from_literal_text(txt).ok() == Rational::from_f64(v).ok() {
+        Node::Value(v)
+    } else {
+        Node::Literal(v, txt.to_string())
+    }
+}
+
+/// parse a value: a conditional, a function call, a variable name, or a number optionally followed by a unit
+fn parse_value(tokens: &[Token], pos: usize) -> Result<(usize, Node), ParseError> {
+    match &tokens[pos].kind {
+        TokenKind::Number(v) if unit_at(tokens, pos + 1).is_some() => {
+            let (new_pos, unit) = parse_unit(tokens, pos + 1)?;
+            Ok((new_pos, Node::Quantity(*v, unit)))
+        }
+        TokenKind::Number(v) => Ok((pos + 1, literal(*v, &tokens[pos].text))),
+        TokenKind::Identifier(name) => match start_parenthesis(tokens, pos + 1) {
+            Some(new_pos) if name == "if" => parse_if(tokens, new_pos),
+            Some(new_pos) => {
+                let (new_pos2, args) = parse_arguments(tokens, new_pos)?;
+                Ok((new_pos2, Node::Call(name.clone(), args)))
+            }
+            None => Ok((pos + 1, Node::Variable(name.clone()))),
+        },
+        _ => Err(ParseError::new(tokens, pos, Expected::Value)),
+    }
+}
+
+/// parse a unit: unit names with optional integer exponents, juxtaposed or separated by `/`
+fn parse_unit(tokens: &[Token], pos: usize) -> Result<(usize, Unit), ParseError> {
+    let mut factors = vec![];
+    let mut pos = pos;
+    loop {
+        // a division only belongs to the unit if a unit name follows
+        let (start, sign) = match tokens[pos].kind {
+            TokenKind::Operator(Operator::Divide) if !factors.is_empty() => (pos + 1, -1),
+            _ => (pos, 1),
+        };
+        let unit = match unit_at(tokens, start) {
+            Some(unit) => unit,
+            None if factors.is_empty() => {
+                return Err(ParseError::new(tokens, start, Expected::Unit))
+            }
+            None => break,
+        };
+        pos = start + 1;
+        let mut exponent = 1;
+        if tokens[pos].kind == TokenKind::Operator(Operator::Power) {
+            pos += 1;
+            if tokens[pos].kind == TokenKind::Operator(Operator::Substract) {
+                exponent = -1;
+                pos += 1;
+            }
+            match tokens[pos].kind {
+                TokenKind::Number(v) if v.fract() == 0.0 && v <= f64::from(i8::MAX) => {
+                    exponent *= v as i32;
+                    pos += 1;
+                }
+                _ => return Err(ParseError::new(tokens, pos, Expected::Exponent)),
             }
         }
-        None => None,
+        factors.push((unit, sign * exponent));
+    }
+    match Unit::compound(&factors) {
+        Some(unit) => Ok((pos, unit)),
+        None => Err(ParseError::new(tokens, pos - 1, Expected::Exponent)),
     }
 }
 
-/// parse a value: an decimal with an optional minus sign
-fn parse_value(chars: &Vec<char>, pos: usize) -> Option<(usize, Node)> {
+/// the known unit named by the token at the given position, if it is not a function call
+fn unit_at(tokens: &[Token], pos: usize) -> Option<Unit> {
+    match (&tokens[pos].kind, tokens.get(pos + 1).map(|t| &t.kind)) {
+        (TokenKind::Identifier(_), Some(TokenKind::OpenParenthesis)) => None,
+        (TokenKind::Identifier(name), _) => Unit::named(name),
+        _ => None,
+    }
+}
+
+/// parse comma separated function arguments, up to and including the closing parenthesis
+fn parse_arguments(tokens: &[Token], pos: usize) -> Result<(usize, Vec<Node>), ParseError> {
+    let mut args = vec![];
+    if tokens[pos].kind == TokenKind::CloseParenthesis {
+        return Ok((pos + 1, args));
+    }
     let mut new_pos = pos;
-    if new_pos < chars.len() && chars[new_pos] == '-' {
-        new_pos += 1;
+    loop {
+        let (arg_pos, arg) = parse_expression(tokens, new_pos)?;
+        args.push(arg);
+        match tokens[arg_pos].kind {
+            TokenKind::Comma => new_pos = arg_pos + 1,
+            TokenKind::CloseParenthesis => return Ok((arg_pos + 1, args)),
+            _ => return Err(ParseError::new(tokens, arg_pos, Expected::Separator)),
+        }
     }
-    while new_pos < chars.len()
-        && (chars[new_pos] == '.' || (chars[new_pos] >= '0' && chars[new_pos] <= '9'))
-    {
-        new_pos += 1;
+}
+
+/// parse the three parts of a conditional, up to and including the closing parenthesis
+fn parse_if(tokens: &[Token], pos: usize) -> Result<(usize, Node), ParseError> {
+    let (pos, condition) = parse_expression(tokens, pos)?;
+    let pos = comma(tokens, pos)?;
+    let (pos, then) = parse_expression(tokens, pos)?;
+    let pos = comma(tokens, pos)?;
+    let (pos, otherwise) = parse_expression(tokens, pos)?;
+    match tokens[pos].kind {
+        TokenKind::CloseParenthesis => Ok((
+            pos + 1,
+            Node::If(Box::new(condition), Box::new(then), Box::new(otherwise)),
+        )),
+        _ => Err(ParseError::new(tokens, pos, Expected::CloseParenthesis)),
+    }
+}
+
+/// match a comma
+fn comma(tokens: &[Token], pos: usize) -> Result<usize, ParseError> {
+    match tokens[pos].kind {
+        TokenKind::Comma => Ok(pos + 1),
+        _ => Err(ParseError::new(tokens, pos, Expected::Comma)),
     }
-    if new_pos > pos {
-        if let Ok(v) = dbg!(chars[pos..new_pos].iter().collect::<String>()).parse() {
-            Some((new_pos, Node::Value(v)))
-        } else {
-            None
+}
+
+/// eval a string, in an environment only containing the built-ins
+pub fn eval(txt: &str) -> Result<Value, CalcError> {
+    let t = parse(txt)?;
+    Ok(eval_with(&t, &Environment::new())?)
+}
+
+/// eval a string exactly, using rational arithmetic
+pub fn eval_exact(txt: &str) -> Result<Value<Rational>, CalcError> {
+    let t = parse(txt)?;
+    Ok(eval_as(&t, &Environment::new())?)
+}
+
+/// eval a string with checked integer arithmetic
+pub fn eval_integer(txt: &str) -> Result<Value<i64>, CalcError> {
+    let t = parse(txt)?;
+    Ok(eval_as(&t, &Environment::new())?)
+}
+
+/// eval a node, looking up variables in the given environment
+pub fn eval_with(t: &Node, env: &Environment) -> Result<Value, EvalError> {
+    eval_term(t, env)
+}
+
+/// eval a node with the given number type, looking up variables in the given environment.
+/// Functions are always computed with floats, their arguments and results being converted.
+pub fn eval_as<N: Number>(t: &Node, env: &Environment) -> Result<Value<N>, EvalError> {
+    eval_term(t, env)
+}
+
+/// Where names are looked up during an evaluation: the parameters of the user defined function
+/// being evaluated, then the environment. Functions only see their own parameters, not the ones of
+/// their callers.
+struct Scope<'a, N> {
+    params: &'a [String],
+    args: Vec<Value<N>>,
+}
+
+impl<'a, N: Number> Scope<'a, N> {
+    /// the value of a variable
+    fn get(&self, env: &Environment, name: &str) -> Result<Value<N>, EvalError> {
+        match self.params.iter().rposition(|p| p == name) {
+            Some(ix) => Ok(self.args[ix].clone()),
+            None => env
+                .get(name)
+                .ok_or_else(|| EvalError::UnboundVariable(name.to_string()))?
+                .convert(),
         }
-    } else {
-        None
     }
 }
 
-/// parse an operator
-fn parse_operator(chars: &Vec<char>, pos: usize) -> Option<(usize, Operator)> {
-    if pos < chars.len() {
-        let ops_with_char = vec![
-            ('+', Operator::Add),
-            ('-', Operator::Substract),
-            ('*', Operator::Multiply),
-            ('/', Operator::Divide),
-        ];
-        for (ch, op) in ops_with_char {
-            if chars[pos] == ch {
-                return Some((pos + 1, op));
+/// What is left to do in an evaluation
+enum Step<'a> {
+    /// push the value of the node
+    Eval(&'a Node),
+    /// replace the value on top by the result of the operator
+    Unary(UnaryOperator),
+    /// replace the two values on top by the result of the operator
+    Binary(Operator),
+    /// `&&` or `||` with the left side on top: keep it if it decides, else evaluate the right side
+    Logic(Operator, &'a Node),
+    /// check the value on top is a boolean
+    Boolean,
+    /// replace the condition on top by the value of the chosen branch
+    Branch(&'a Node, &'a Node),
+    /// convert the quantity on top to the unit
+    Convert(&'a Unit),
+    /// move the value on top to the float arguments of a built-in function
+    Argument,
+    /// replace the given number of float arguments by the result of the built-in function
+    Function(&'a str, &'a Function, usize),
+    /// replace the given number of values on top by the result of the user defined function
+    Definition(&'a Definition, usize),
+    /// leave the scope of a user defined function
+    Return,
+}
+
+/// eval a term with explicit stacks rather than recursion, so that calls of user defined functions
+/// nested up to the limit of the environment don't overflow the native stack.
+/// `&&`, `||` and conditionals only evaluate what they need
+fn eval_term<'a, N: Number>(t: &'a Node, env: &'a Environment) -> Result<Value<N>, EvalError> {
+    let mut steps = vec![Step::Eval(t)];
+    let mut values: Vec<Value<N>> = vec![];
+    let mut floats: Vec<f64> = vec![];
+    let mut scopes = vec![Scope {
+        params: &[],
+        args: vec![],
+    }];
+    while let Some(step) = steps.pop() {
+        match step {
+            Step::Eval(t) => match t {
+                Node::Value(v) => values.push(Value::Number(N::from_f64(*v)?)),
+                Node::Literal(v, txt) => values.push(Value::Number(N::from_literal(*v, txt)?)),
+                Node::Variable(name) => {
+                    let scope = scopes.last().expect("the top level scope");
+                    values.push(scope.get(env, name)?);
+                }
+                Node::Call(name, args) => match env.definition(name) {
+                    Some(f) => {
+                        steps.push(Step::Definition(f, args.len()));
+                        steps.extend(args.iter().rev().map(Step::Eval));
+                    }
+                    None => {
+                        let f = env
+                            .function(name)
+                            .ok_or_else(|| EvalError::UnknownFunction(name.to_string()))?;
+                        steps.push(Step::Function(name, f, args.len()));
+                        for a in args.iter().rev() {
+                            steps.extend([Step::Argument, Step::Eval(a)]);
+                        }
+                    }
+                },
+                Node::SubNode(t) => steps.push(Step::Eval(t)),
+                Node::Unary(op, t) => steps.extend([Step::Unary(*op), Step::Eval(t)]),
+                Node::Binary(op @ (Operator::And | Operator::Or), t1, t2) => {
+                    steps.extend([Step::Logic(*op, t2), Step::Eval(t1)])
+                }
+                Node::Binary(op, t1, t2) => {
+                    steps.extend([Step::Binary(*op), Step::Eval(t2), Step::Eval(t1)])
+                }
+                Node::If(c, t1, t2) => steps.extend([Step::Branch(t1, t2), Step::Eval(c)]),
+                Node::Quantity(v, unit) => {
+                    values.push(Value::from_quantity(Quantity::new(*v, unit.clone()))?)
+                }
+                Node::Convert(t, unit) => steps.extend([Step::Convert(unit), Step::Eval(t)]),
+            },
+            Step::Unary(op) => {
+                let a = pop(&mut values);
+                values.push(Value::unary(op, a)?);
+            }
+            Step::Binary(op) => {
+                let b = pop(&mut values);
+                let a = pop(&mut values);
+                values.push(Value::binary(op, a, b)?);
+            }
+            Step::Logic(op, t2) => {
+                let a = pop(&mut values).boolean()?;
+                if a == (op == Operator::Or) {
+                    values.push(Value::Bool(a));
+                } else {
+                    steps.extend([Step::Boolean, Step::Eval(t2)]);
+                }
+            }
+            Step::Boolean => {
+                let b = pop(&mut values).boolean()?;
+                values.push(Value::Bool(b));
+            }
+            Step::Branch(t1, t2) => {
+                let c = pop(&mut values).boolean()?;
+                steps.push(Step::Eval(if c { t1 } else { t2 }));
+            }
+            Step::Convert(unit) => {
+                let a = pop(&mut values);
+                values.push(a.to_unit(unit)?);
+            }
+            Step::Argument => floats.push(pop(&mut values).number()?.to_f64()),
+            Step::Function(name, f, count) => {
+                let args = floats.split_off(floats.len() - count);
+                values.push(Value::Number(N::from_f64(f.call(name, &args)?)?));
+            }
+            Step::Definition(f, count) => {
+                let args = values.split_off(values.len() - count);
+                if args.len() != f.params.len() {
+                    return Err(EvalError::WrongArity {
+                        name: f.name.clone(),
+                        found: args.len(),
+                    });
+                }
+                // the top level scope is not a call
+                if scopes.len() > env.max_depth {
+                    return Err(EvalError::RecursionLimit {
+                        name: f.name.clone(),
+                        limit: env.max_depth,
+                    });
+                }
+                scopes.push(Scope {
+                    params: &f.params,
+                    args,
+                });
+                steps.extend([Step::Return, Step::Eval(&f.body)]);
+            }
+            Step::Return => {
+                scopes.pop();
             }
         }
     }
-    None
+    Ok(pop(&mut values))
 }
 
-/// eval a string
-pub fn eval(txt: &str) -> f64 {
-    match parse(txt) {
-        Some(t) => eval_term(&t),
-        None => panic!("Cannot parse {}", txt),
-    }
+/// the value on top of the evaluation stack
+fn pop<N>(values: &mut Vec<Value<N>>) -> Value<N> {
+    values.pop().expect("a value on the evaluation stack")
 }
 
-/// eval a term, recursively
-fn eval_term(t: &Node) -> f64 {
+/// simplify a node: fold constant sub-expressions, remove neutral elements and redundant sub nodes
+pub fn simplify(t: Node) -> Node {
     match t {
-        Node::Value(v) => *v,
-        Node::SubNode(t) => eval_term(t),
-        Node::Binary(Operator::Add, t1, t2) => eval_term(t1) + eval_term(t2),
-        Node::Binary(Operator::Substract, t1, t2) => eval_term(t1) - eval_term(t2),
-        Node::Binary(Operator::Multiply, t1, t2) => eval_term(t1) * eval_term(t2),
-        Node::Binary(Operator::Divide, t1, t2) => eval_term(t1) / eval_term(t2),
+        Node::SubNode(n) => simplify(*n),
+        Node::Call(name, args) => Node::Call(name, args.into_iter().map(simplify).collect()),
+        Node::Unary(UnaryOperator::Negate, n) => match simplify(*n) {
+            // `--b` fails on a boolean or a quantity, keep it for the evaluation to report
+            Node::Unary(UnaryOperator::Negate, n) if !is_number(&n) => Node::Unary(
+                UnaryOperator::Negate,
+                Box::new(Node::Unary(UnaryOperator::Negate, n)),
+            ),
+            n => negate(n),
+        },
+        Node::Unary(op, n) => Node::Unary(op, Box::new(simplify(*n))),
+        Node::If(c, n1, n2) => Node::If(
+            Box::new(simplify(*c)),
+            Box::new(simplify(*n1)),
+            Box::new(simplify(*n2)),
+        ),
+        Node::Convert(n, unit) => Node::Convert(Box::new(simplify(*n)), unit),
+        Node::Binary(op, n1, n2) => match (op, simplify(*n1), simplify(*n2)) {
+            // bitwise operators on non integers are left for the evaluation to report, and
+            // infinities and NaN are kept as formulas, having no literal to display as
+            (op, Node::Value(v1), Node::Value(v2)) if op.is_arithmetic() => {
+                match f64::binary(op, &v1, &v2) {
+                    Ok(v) if v.is_finite() => Node::Value(v),
+                    _ => Node::Binary(op, Box::new(Node::Value(v1)), Box::new(Node::Value(v2))),
+                }
+            }
+            // neutral elements are only dropped from numbers: `b + 0` fails on a boolean,
+            // and `3 m + 0` on a quantity
+            (Operator::Add, Node::Value(0.0), n)
+            | (Operator::Add, n, Node::Value(0.0))
+            | (Operator::Substract, n, Node::Value(0.0))
+            | (Operator::Multiply, Node::Value(1.0), n)
+            | (Operator::Multiply, n, Node::Value(1.0))
+            | (Operator::Divide, n, Node::Value(1.0))
+            | (Operator::Power, n, Node::Value(1.0))
+                if is_number(&n) =>
+            {
+                n
+            }
+            (Operator::Substract, Node::Value(0.0), n) if is_number(&n) => negate(n),
+            (op, n1, n2) => Node::Binary(op, Box::new(n1), Box::new(n2)),
+        },
+        n => n,
+    }
+}
+
+/// does the node evaluate to a plain number, when it evaluates at all:
+/// variables and calls may give booleans or quantities
+fn is_number(n: &Node) -> bool {
+    match n {
+        Node::Value(_) | Node::Literal(_, _) => true,
+        Node::SubNode(n) | Node::Unary(UnaryOperator::Negate, n) => is_number(n),
+        Node::Binary(op, n1, n2) => op.is_arithmetic() && is_number(n1) && is_number(n2),
+        Node::If(_, n1, n2) => is_number(n1) && is_number(n2),
+        _ => false,
+    }
+}
+
+/// negate a simplified node, folding constants and double negations
+fn negate(n: Node) -> Node {
+    match n {
+        Node::Value(v) => Node::Value(-v),
+        Node::Unary(UnaryOperator::Negate, n) => *n,
+        n => Node::Unary(UnaryOperator::Negate, Box::new(n)),
     }
 }
 
+/// symbolic derivative of a node with respect to the given variable
+pub fn derive(t: &Node, var: &str) -> Result<Node, EvalError> {
+    derive_term(t, var).map(simplify)
+}
+
+/// derivative of a term, recursively
+fn derive_term(t: &Node, var: &str) -> Result<Node, EvalError> {
+    Ok(match t {
+        Node::Value(_) | Node::Literal(_, _) | Node::Quantity(_, _) => Node::Value(0.0),
+        Node::Convert(_, _) => return Err(EvalError::NotDifferentiable(String::from("to"))),
+        Node::Variable(name) => Node::Value(if name == var { 1.0 } else { 0.0 }),
+        Node::SubNode(n) => derive_term(n, var)?,
+        Node::Unary(UnaryOperator::Negate, n) => negate(derive_term(n, var)?),
+        Node::Unary(op, _) => return Err(EvalError::NotDifferentiable(op.symbol().into())),
+        // piecewise: the condition is constant almost everywhere
+        Node::If(c, n1, n2) => match (derive_term(n1, var)?, derive_term(n2, var)?) {
+            (d1, d2) if is_zero(&d1) && is_zero(&d2) => Node::Value(0.0),
+            (d1, d2) => Node::If(c.clone(), Box::new(d1), Box::new(d2)),
+        },
+        Node::Binary(op, u, v) => {
+            let (du, dv) = (derive_term(u, var)?, derive_term(v, var)?);
+            let (u, v) = (u.as_ref().clone(), v.as_ref().clone());
+            match op {
+                Operator::Add => sum(du, dv),
+                Operator::Substract => difference(du, dv),
+                // (uv)' = u'v + uv'
+                Operator::Multiply => sum(product(du, v), product(u, dv)),
+                // (u/v)' = (u'v - uv') / v^2
+                Operator::Divide => quotient(
+                    difference(product(du, v.clone()), product(u, dv)),
+                    power(v, Node::Value(2.0)),
+                ),
+                // u % v is u - v * trunc(u/v), trunc being constant almost everywhere
+                Operator::Modulo if is_zero(&dv) => du,
+                Operator::Modulo => return Err(EvalError::NotDifferentiable(op.symbol().into())),
+                // (u^c)' = c u^(c-1) u'
+                Operator::Power if is_zero(&dv) => product(
+                    product(v.clone(), power(u, difference(v, Node::Value(1.0)))),
+                    du,
+                ),
+                // (u^v)' = u^v (v' ln(u) + v u' / u)
+                Operator::Power => product(
+                    power(u.clone(), v.clone()),
+                    sum(
+                        product(dv, call("ln", u.clone())),
+                        quotient(product(v, du), u),
+                    ),
+                ),
+                _ => return Err(EvalError::NotDifferentiable(op.symbol().into())),
+            }
+        }
+        Node::Call(name, args) => {
+            let dargs = args
+                .iter()
+                .map(|a| derive_term(a, var))
+                .collect::<Result<Vec<_>, _>>()?;
+            if dargs.iter().all(is_zero) {
+                return Ok(Node::Value(0.0));
+            }
+            if args.len() != 1 {
+                return Err(EvalError::NotDifferentiable(name.clone()));
+            }
+            let u = args[0].clone();
+            // chain rule: f(u)' = f'(u) u'
+            let df = match name.as_str() {
+                "sin" => call("cos", u),
+                "cos" => negate(call("sin", u)),
+                "tan" => sum(Node::Value(1.0), power(call("tan", u), Node::Value(2.0))),
+                "sqrt" => quotient(Node::Value(0.5), call("sqrt", u)),
+                "ln" => quotient(Node::Value(1.0), u),
+                "exp" => call("exp", u),
+                "abs" => quotient(u.clone(), call("abs", u)),
+                "round" => Node::Value(0.0),
+                "min" | "max" => Node::Value(1.0),
+                _ => return Err(EvalError::NotDifferentiable(name.clone())),
+            };
+            product(df, dargs.into_iter().next().unwrap())
+        }
+    })
+}
+
+/// is the node the constant zero
+fn is_zero(n: &Node) -> bool {
+    *n == Node::Value(0.0)
+}
+
+/// is the node the constant one
+fn is_one(n: &Node) -> bool {
+    *n == Node::Value(1.0)
+}
+
+/// build a sum, dropping zeros
+fn sum(a: Node, b: Node) -> Node {
+    if is_zero(&a) {
+        b
+    } else if is_zero(&b) {
+        a
+    } else {
+        simplify(Node::Binary(Operator::Add, Box::new(a), Box::new(b)))
+    }
+}
+
+/// build a difference, dropping zeros
+fn difference(a: Node, b: Node) -> Node {
+    if is_zero(&b) {
+        a
+    } else {
+        simplify(Node::Binary(Operator::Substract, Box::new(a), Box::new(b)))
+    }
+}
+
+/// build a product, treating zero as absorbing, dropping ones and putting constants first
+fn product(a: Node, b: Node) -> Node {
+    if is_zero(&a) || is_zero(&b) {
+        Node::Value(0.0)
+    } else if is_one(&a) {
+        b
+    } else if is_one(&b) {
+        a
+    } else if matches!(b, Node::Value(_)) && !matches!(a, Node::Value(_)) {
+        product(b, a)
+    } else {
+        simplify(Node::Binary(Operator::Multiply, Box::new(a), Box::new(b)))
+    }
+}
+
+/// build a quotient, the numerator being zero giving zero
+fn quotient(a: Node, b: Node) -> Node {
+    if is_zero(&a) {
+        Node::Value(0.0)
+    } else {
+        simplify(Node::Binary(Operator::Divide, Box::new(a), Box::new(b)))
+    }
+}
+
+/// build a power, dropping a unit exponent
+fn power(a: Node, b: Node) -> Node {
+    if is_one(&b) {
+        a
+    } else {
+        simplify(Node::Binary(Operator::Power, Box::new(a), Box::new(b)))
+    }
+}
+
+/// build a call to a one argument function
+fn call(name: &str, arg: Node) -> Node {
+    Node::Call(name.to_string(), vec![arg])
+}
+
 pub fn can_make_arithmetic_progression(mut arr: Vec<i32>) -> bool {
     arr.sort();
     let diff = arr[1]-arr[0];
@@ -174,17 +1363,1098 @@ mod tests {
 
     #[test]
     fn test_eval() {
-        assert_eq!(2.0, eval("2"));
-        assert_eq!(4.0, eval("2+2"));
-        assert_eq!(11.0 / 4.0, eval("2+3/4"));
-        assert_eq!(2.0, eval("2*3-4"));
-        assert_eq!(3.0, eval("1+2*3-4"));
-        assert_eq!(89.0 / 6.0, eval("2*(3+4)+5/6"));
-        assert_eq!(14.0, eval("2 * (3 -1) + 2 * 5"));
-        assert_eq!(7000.0, eval("2 * (3 + (4 * 5 + (6 * 7) * 8) - 9) * 10"));
-        assert_eq!(-9.0 / 4.0, eval("2*-3--4+-.25"));
-        assert_eq!(1.5, eval("1 - 5 * 2 / 20 + 1"));
-        assert_eq!(3.5, eval("2 * (3 + ((5) / (7 - 11)))"));
+        assert_eq!(Ok(Value::Number(2.0)), eval("2"));
+        assert_eq!(Ok(Value::Number(4.0)), eval("2+2"));
+        assert_eq!(Ok(Value::Number(11.0 / 4.0)), eval("2+3/4"));
+        assert_eq!(Ok(Value::Number(2.0)), eval("2*3-4"));
+        assert_eq!(Ok(Value::Number(3.0)), eval("1+2*3-4"));
+        assert_eq!(Ok(Value::Number(89.0 / 6.0)), eval("2*(3+4)+5/6"));
+        assert_eq!(Ok(Value::Number(14.0)), eval("2 * (3 -1) + 2 * 5"));
+        assert_eq!(
+            Ok(Value::Number(7000.0)),
+            eval("2 * (3 + (4 * 5 + (6 * 7) * 8) - 9) * 10")
+        );
+        assert_eq!(Ok(Value::Number(-9.0 / 4.0)), eval("2*-3--4+-.25"));
+        assert_eq!(Ok(Value::Number(1.5)), eval("1 - 5 * 2 / 20 + 1"));
+        assert_eq!(Ok(Value::Number(3.5)), eval("2 * (3 + ((5) / (7 - 11)))"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 3,
+                expected: Expected::End,
+                found: Some(String::from(")")),
+            })),
+            eval("2+2)")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 6,
+                expected: Expected::End,
+                found: Some(String::from(")")),
+            })),
+            eval("2 + 2 )")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 2,
+                expected: Expected::Value,
+                found: None,
+            })),
+            eval("2+")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 4,
+                expected: Expected::CloseParenthesis,
+                found: None,
+            })),
+            eval("(2+2")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 2,
+                expected: Expected::Value,
+                found: Some(String::from("#")),
+            })),
+            eval("1*#")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 0,
+                expected: Expected::Value,
+                found: Some(String::from("1.2.3")),
+            })),
+            eval("1.2.3")
+        );
+        assert_eq!(
+            "expected an operator or the end of input at position 3, found ')'",
+            parse("2+2)").unwrap_err().to_string()
+        );
+        // whitespace separates tokens instead of being ignored
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 2,
+                expected: Expected::End,
+                found: Some(String::from("2")),
+            })),
+            eval("1 2 + 3")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 4,
+                expected: Expected::End,
+                found: Some(String::from("x_2")),
+            })),
+            eval("0x1 x_2")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 4,
+                expected: Expected::Value,
+                found: Some(String::from("=")),
+            })),
+            eval("1 < = 2")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 5,
+                expected: Expected::Value,
+                found: Some(String::from("0b12")),
+            })),
+            eval("1 +\t 0b12")
+        );
+        assert_eq!(Ok(Value::Number(12.0)), eval("\t12 "));
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut env = Environment::new();
+        env.set("rate", 12.5);
+        env.set("hours", 8.0);
+        let n = parse("rate * hours").unwrap();
+        assert_eq!(Ok(Value::Number(100.0)), eval_with(&n, &env));
+        assert_eq!(Some(Value::Number(12.5)), env.set("rate", 10.0));
+        assert_eq!(Ok(Value::Number(80.0)), eval_with(&n, &env));
+        assert_eq!(
+            Ok(Value::Number(88.0)),
+            eval_with(&parse("(rate + 1) * hours").unwrap(), &env)
+        );
+        assert_eq!(
+            Ok(Value::Number(1.0)),
+            eval_with(&parse("_x1 - x_2").unwrap(), &{
+                let mut env = Environment::new();
+                env.set("_x1", 3.0);
+                env.set("x_2", 2.0);
+                env
+            })
+        );
+
+        let n = parse("rate * days").unwrap();
+        assert_eq!(
+            Err(EvalError::UnboundVariable(String::from("days"))),
+            eval_with(&n, &env)
+        );
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::UnboundVariable(String::from(
+                "rate"
+            )))),
+            eval("rate * 2")
+        );
+        assert_eq!(
+            "unbound variable 'rate'",
+            eval("rate * 2").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(
+            Ok(Value::Number(2.0_f64.sqrt() * consts::PI)),
+            eval("sqrt(2) * pi")
+        );
+        assert_eq!(Ok(Value::Number(consts::E)), eval("e"));
+        assert_eq!(Ok(Value::Number(1.0)), eval("cos(0) + sin(0)"));
+        assert_eq!(Ok(Value::Number(3.0)), eval("round(ln(exp(2.6)))"));
+        assert_eq!(Ok(Value::Number(4.0)), eval("abs(-4)"));
+        assert_eq!(Ok(Value::Number(-1.0)), eval("min(3, -1, 2)"));
+        assert_eq!(Ok(Value::Number(5.0)), eval("max(2*2, 1+2*2)"));
+        assert_eq!(Ok(Value::Number(7.0)), eval("max(1)+min((3),6)*2"));
+
+        let mut env = Environment::new();
+        env.set("a", 4.0);
+        env.set("b", 9.0);
+        let n = parse("max(a, b, 3)").unwrap();
+        assert_eq!(Ok(Value::Number(9.0)), eval_with(&n, &env));
+        env.define_function("hypot", 2, |a| a[0].hypot(a[1]));
+        env.define_variadic("sum", |a| a.iter().sum());
+        assert_eq!(
+            Ok(Value::Number(5.0)),
+            eval_with(&parse("hypot(3, a)").unwrap(), &env)
+        );
+        assert_eq!(
+            Ok(Value::Number(16.0)),
+            eval_with(&parse("sum(a, b, 3)").unwrap(), &env)
+        );
+
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::UnknownFunction(String::from(
+                "hypot"
+            )))),
+            eval("hypot(3, 4)")
+        );
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::WrongArity {
+                name: String::from("sin"),
+                found: 2
+            })),
+            eval("sin(1, 2)")
+        );
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::WrongArity {
+                name: String::from("max"),
+                found: 0
+            })),
+            eval("max()")
+        );
+        assert_eq!(
+            Err(EvalError::UnboundVariable(String::from("pi"))),
+            eval_with(&parse("pi").unwrap(), &Environment::empty())
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 8,
+                expected: Expected::Separator,
+                found: None,
+            })),
+            eval("max(1, 2")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 6,
+                expected: Expected::Value,
+                found: Some(String::from(",")),
+            })),
+            eval("max(1,,2)")
+        );
+    }
+
+    #[test]
+    fn test_definitions() {
+        let mut env = Environment::new();
+        let define = |env: &mut Environment, txt| match parse_statement(txt).unwrap() {
+            Statement::Definition(d) => env.define(d),
+            s => panic!("not a definition: {:?}", s),
+        };
+        define(&mut env, "f(x, y) = x^2 + y");
+        assert_eq!(
+            Ok(Value::Number(13.0)),
+            eval_with(&parse("f(3, 4)").unwrap(), &env)
+        );
+        assert_eq!(
+            "f(x, y) = x ^ 2 + y",
+            env.definition("f").unwrap().to_string()
+        );
+        define(&mut env, "fact(n) = if(n <= 1, 1, n * fact(n - 1))");
+        assert_eq!(
+            Ok(Value::Number(3_628_800.0)),
+            eval_with(&parse("fact(10)").unwrap(), &env)
+        );
+        let n = parse("fact(25) / fact(24)").unwrap();
+        assert_eq!(
+            Ok(Value::Number(Rational::from_f64(25.0).unwrap())),
+            eval_as::<Rational>(&n, &env)
+        );
+
+        // lexical scoping: the body sees the environment, not the parameters of the caller
+        env.set("y", 100.0);
+        define(&mut env, "g(x) = x + y");
+        define(&mut env, "h(y) = g(1)");
+        assert_eq!(
+            Ok(Value::Number(101.0)),
+            eval_with(&parse("h(5)").unwrap(), &env)
+        );
+        assert_eq!(
+            Err(EvalError::UnboundVariable(String::from("x"))),
+            eval_with(&parse("x").unwrap(), &env)
+        );
+        // parameters shadow variables, definitions replace built-ins
+        define(&mut env, "sin(pi) = pi * 2");
+        assert_eq!(
+            Ok(Value::Number(6.0)),
+            eval_with(&parse("sin(3)").unwrap(), &env)
+        );
+        assert!(env.function("sin").is_none());
+        env.define_function("sin", 1, |a| a[0].sin());
+        assert!(env.definition("sin").is_none());
+
+        assert_eq!(
+            Err(EvalError::WrongArity {
+                name: String::from("f"),
+                found: 1
+            }),
+            eval_with(&parse("f(1)").unwrap(), &env)
+        );
+        define(&mut env, "loop(x) = loop(x + 1)");
+        assert_eq!(
+            Err(EvalError::RecursionLimit {
+                name: String::from("loop"),
+                limit: DEFAULT_MAX_DEPTH
+            }),
+            eval_with(&parse("loop(0)").unwrap(), &env)
+        );
+        env.set_max_depth(5);
+        assert_eq!(
+            Ok(Value::Number(120.0)),
+            eval_with(&parse("fact(5)").unwrap(), &env)
+        );
+        assert_eq!(
+            "function 'fact' exceeded the limit of 5 nested calls",
+            eval_with(&parse("fact(6)").unwrap(), &env)
+                .unwrap_err()
+                .to_string()
+        );
+        // deep calls take memory, not native stack
+        define(&mut env, "down(n) = if(n <= 0, 0, 1 + down(n - 1))");
+        env.set_max_depth(100_010);
+        assert_eq!(
+            Ok(Value::Number(100_000.0)),
+            eval_with(&parse("down(100000)").unwrap(), &env)
+        );
+        env.set_max_depth(1_000_000);
+        assert_eq!(
+            Err(EvalError::RecursionLimit {
+                name: String::from("loop"),
+                limit: 1_000_000
+            }),
+            eval_with(&parse("loop(0)").unwrap(), &env)
+        );
+    }
+
+    #[test]
+    fn test_parse_statement() {
+        assert_eq!(
+            Ok(Statement::Definition(Definition::new(
+                "one",
+                vec![],
+                Node::Value(1.0)
+            ))),
+            parse_statement("one() = 1")
+        );
+        assert_eq!(
+            Ok(Statement::Expression(parse("f(1) == 2").unwrap())),
+            parse_statement("f(1) == 2")
+        );
+        assert_eq!(
+            Err(ParseError {
+                pos: 2,
+                expected: Expected::Parameter,
+                found: Some(String::from("2")),
+            }),
+            parse_statement("f(2) = 3")
+        );
+        for empty in ["", "   "] {
+            assert_eq!(
+                Err(ParseError {
+                    pos: empty.len(),
+                    expected: Expected::Value,
+                    found: None,
+                }),
+                parse_statement(empty)
+            );
+        }
+        assert_eq!(
+            Err(ParseError {
+                pos: 5,
+                expected: Expected::Assign,
+                found: Some(String::from("+")),
+            }),
+            parse_statement("f(x) + 1 = 3")
+        );
+        assert_eq!(
+            Err(ParseError {
+                pos: 4,
+                expected: Expected::Separator,
+                found: Some(String::from("y")),
+            }),
+            parse_statement("f(x y) = 3")
+        );
+        assert_eq!(
+            Err(ParseError {
+                pos: 2,
+                expected: Expected::End,
+                found: Some(String::from("=")),
+            }),
+            parse_statement("x = 3")
+        );
+        assert_eq!(
+            Err(ParseError {
+                pos: 9,
+                expected: Expected::End,
+                found: Some(String::from("=")),
+            }),
+            parse_statement("f(x) = 1 = 2")
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(Ok(Value::Number(8.0)), eval("2^3"));
+        assert_eq!(Ok(Value::Number(2.0_f64.powf(9.0))), eval("2^3^2"));
+        assert_eq!(Ok(Value::Number(24.0)), eval("3*2^3"));
+        assert_eq!(Ok(Value::Number(64.0)), eval("(2^3)^2"));
+        assert_eq!(Ok(Value::Number(0.5)), eval("2^-1"));
+        assert_eq!(Ok(Value::Number(1.0)), eval("7 % 3"));
+        assert_eq!(Ok(Value::Number(3.0)), eval("1 + 8 % 3 * 2 - 2"));
+        assert_eq!(Ok(Value::Number(-1.0)), eval("-7 % 3"));
+        assert_eq!(Ok(Value::Number(-5.0)), eval("-(2+3)"));
+        assert_eq!(Ok(Value::Number(-4.0)), eval("-2^2"));
+        assert_eq!(Ok(Value::Number(4.0)), eval("(-2)^2"));
+        assert_eq!(Ok(Value::Number(5.0)), eval("--5"));
+        assert_eq!(Ok(Value::Number(-6.0)), eval("-2*3"));
+        assert_eq!(Ok(Value::Number(-1.0)), eval("-cos(0)"));
+        assert_eq!(Ok(Value::Number(3.0)), eval("2 - -1"));
+
+        let mut env = Environment::new();
+        env.set("x", 3.0);
+        assert_eq!(
+            Ok(Value::Number(-9.0)),
+            eval_with(&parse("-x^2").unwrap(), &env)
+        );
+        assert_eq!(
+            Ok(Value::Number(3.0)),
+            eval_with(&parse("x - -x + -x").unwrap(), &env)
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 1,
+                expected: Expected::Value,
+                found: None,
+            })),
+            eval("-")
+        );
+    }
+
+    #[test]
+    fn test_booleans() {
+        let t = Ok(Value::Bool(true));
+        let f = Ok(Value::Bool(false));
+        assert_eq!(t, eval("1 < 2"));
+        assert_eq!(f, eval("1 + 1 > 2"));
+        assert_eq!(t, eval("2 <= 2 && 3 >= 4 - 1"));
+        assert_eq!(t, eval("1 == 1 != false"));
+        assert_eq!(t, eval("false || !false"));
+        assert_eq!(f, eval("!(1 < 2 || 1 / 0 > 0)"));
+        assert_eq!(t, eval("true || false && false"));
+        assert_eq!(f, eval("(true || false) && false"));
+        assert_eq!(Ok(Value::Number(2.0)), eval("if(1 > 2, 1, 2)"));
+        assert_eq!(Ok(Value::Number(-1.0)), eval("-if(pi > 3, 1, 0)"));
+        assert_eq!(f, eval("if(true, false, 1)"));
+
+        // short-circuits: the other side would be a type error
+        assert_eq!(f, eval("false && 1"));
+        assert_eq!(t, eval("true || 1"));
+        assert_eq!(Ok(Value::Number(1.0)), eval("if(true, 1, x)"));
+        let mismatch =
+            |expected, found| Err(CalcError::Eval(EvalError::TypeMismatch { expected, found }));
+        assert_eq!(
+            mismatch(ValueType::Bool, ValueType::Number),
+            eval("true && 1")
+        );
+        assert_eq!(
+            mismatch(ValueType::Number, ValueType::Bool),
+            eval("1 + true")
+        );
+        assert_eq!(
+            mismatch(ValueType::Number, ValueType::Bool),
+            eval("1 == true")
+        );
+        assert_eq!(mismatch(ValueType::Bool, ValueType::Number), eval("!1"));
+        assert_eq!(
+            mismatch(ValueType::Bool, ValueType::Number),
+            eval("if(1, 2, 3)")
+        );
+        assert_eq!(
+            mismatch(ValueType::Number, ValueType::Bool),
+            eval("sin(true)")
+        );
+        assert_eq!(
+            "expected a boolean, found a number",
+            eval("if(0, 1, 2)").unwrap_err().to_string()
+        );
+
+        let exact = |n, d| Ok(Value::Number(Rational::new(n, d).unwrap()));
+        assert_eq!(exact(3, 10), eval_exact("if(0.1 + 0.2 == 0.3, 0.3, 0)"));
+        assert_eq!(f, eval("0.1 + 0.2 == 0.3"));
+        assert_eq!(Ok(Value::Bool(true)), eval_integer("7 / 2 == 3"));
+
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 6,
+                expected: Expected::Comma,
+                found: Some(String::from(")")),
+            })),
+            eval("if(1,2)")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 8,
+                expected: Expected::CloseParenthesis,
+                found: Some(String::from(",")),
+            })),
+            eval("if(1,2,3,4)")
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        let n = |v: f64| Ok(Value::Number(v));
+        assert_eq!(n(255.0), eval("0xFF"));
+        assert_eq!(n(255.0), eval("0xff"));
+        assert_eq!(n(10.0), eval("0b1010"));
+        assert_eq!(n(511.0), eval("0o777"));
+        assert_eq!(n(0.001), eval("1e-3"));
+        assert_eq!(n(1500.0), eval("1.5E3"));
+        assert_eq!(n(25.0), eval("2.5e+1"));
+        assert_eq!(n(1_000_000.0), eval("1_000_000"));
+        assert_eq!(n(3_735_928_559.0), eval("0xdead_beef"));
+        assert_eq!(n(1234.5), eval("1_234.5"));
+        assert_eq!(n(0.0), eval("0"));
+        assert_eq!(n(-16.0), eval("-0x10"));
+        assert_eq!(n(3.0), eval("0b1 + 0o2"));
+        assert_eq!(
+            n(2.0 * consts::E),
+            eval("2 * e"),
+            "e alone is still the constant"
+        );
+        for txt in ["0b102", "0x", "1__0", "1_", "1_.5", "0xG", "0x_1", "1.2.3"].iter() {
+            assert_eq!(
+                Err(CalcError::Parse(ParseError {
+                    pos: 0,
+                    expected: Expected::Value,
+                    found: Some(txt.to_string()),
+                })),
+                eval(txt)
+            );
+        }
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 1,
+                expected: Expected::End,
+                found: Some(String::from("e")),
+            })),
+            eval("1e")
+        );
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let n = |v: f64| Ok(Value::Number(v));
+        assert_eq!(n(8.0), eval("0b1100 & 0b1010"));
+        assert_eq!(n(14.0), eval("0b1100 | 0b1010"));
+        assert_eq!(n(6.0), eval("0b1100 ^^ 0b1010"));
+        assert_eq!(n(1024.0), eval("1 << 10"));
+        assert_eq!(n(15.0), eval("0xF0 >> 4"));
+        assert_eq!(n(-2.0), eval("-8 >> 2"));
+        assert_eq!(n(8.0), eval("2 ^ 3 ^^ 0"), "power binds tighter than xor");
+        assert_eq!(
+            n(10.0),
+            eval("1 << 2 + 1 | 2"),
+            "shifts bind looser than +, | looser than shifts"
+        );
+        assert_eq!(n(1.0), eval("6 & 3 ^^ 3 | 1 & 0"));
+        assert_eq!(Ok(Value::Bool(true)), eval("5 & 1 == 1"));
+        assert_eq!(Ok(Value::Bool(true)), eval("1 << 3 <= 8"));
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::NotInteger(String::from("1.5")))),
+            eval("1.5 & 1")
+        );
+        assert_eq!(
+            "bitwise operators need integers, found 1.5",
+            eval("1 | 1.5").unwrap_err().to_string()
+        );
+        assert_eq!(Err(CalcError::Eval(EvalError::Overflow)), eval("1 << 64"));
+        assert_eq!(Err(CalcError::Eval(EvalError::Overflow)), eval("3 << 62"));
+        assert_eq!(Err(CalcError::Eval(EvalError::Overflow)), eval("1 >> -1"));
+        assert_eq!(Ok(Value::Number(6)), eval_integer("0b110 ^^ 0"));
+        assert_eq!(
+            Ok(Value::Number(Rational::from_integer(4))),
+            eval_exact("1/2 * 4 << 1")
+        );
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::NotInteger(String::from("1/2")))),
+            eval_exact("1/2 & 1")
+        );
+        assert_eq!("1 << 2 + 1 | 2", parse("(1<<(2+1))|2").unwrap().to_string());
+        assert_eq!("(1 | 2) & 3", parse("(1|2)&3").unwrap().to_string());
+        assert_eq!("6", simplify(parse("3 << 1").unwrap()).to_string());
+        assert_eq!("1.5 & 1", simplify(parse("1.5 & 1").unwrap()).to_string());
+    }
+
+    #[test]
+    fn test_units() {
+        let q = |txt: &str| eval(txt).unwrap().to_string();
+        assert_eq!("6 m s^-1", q("3 m * 2 s^-1"));
+        assert_eq!("5300 m", q("5 km + 300 m"));
+        assert_eq!("2 mi", q("1 mi + 1 mi"));
+        assert_eq!("15 km", q("3 * 5 km"));
+        assert_eq!("2.5 km", q("5 km / 2"));
+        // dimension exponents are checked like the powers
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::NotRepresentable(String::from(
+                "(m^2000000000) * (m^2000000000)"
+            )))),
+            eval("(1 m)^2000000000 * (1 m)^2000000000")
+        );
+        assert!(eval("(1 m)^2000000000 / (1 m)^-2000000000").is_err());
+        assert!(eval("(1 m)^2000000000 / (1 m)^2000000000").is_ok());
+        assert_eq!("36 km h^-1", q("10 m s^-1 to km h^-1"));
+        assert_eq!("36 km h^-1", q("10 m/s to km/h"));
+        assert_eq!("6.2137119223733395 mi", q("10 km to mi"));
+        assert_eq!("6.2137 mi", q("round(10 km / 1 mi * 10^4) / 10^4 * 1 mi"));
+        assert_eq!("9 m^2", q("(3 m)^2"));
+        assert_eq!("3 m^2", q("3 m^2"));
+        assert_eq!("3 m", q("sqrt(9 m^2 * 1 m^-2) * 1 m"));
+        assert_eq!("2 m", q("(4 m^2)^0.5"));
+        assert_eq!("1000 m kg s^-2", q("1 t * 1 m / 1 s^2"));
+        assert_eq!("1000 N", q("1 t * 1 m / 1 s^2 to N"));
+        assert_eq!("3600000 J", q("1 kWh to J"));
+        assert_eq!("-2 m", q("-2 m"));
+        assert_eq!("0.5 m", q("3.5 m % 1 m"));
+        assert_eq!("1000 L", q("1 m^3 to L"));
+        assert_eq!(Ok(Value::Number(1000.0)), eval("1 km / 1 m"));
+        assert_eq!(Ok(Value::Bool(true)), eval("1 mi > 1 km && 100 cm == 1 m"));
+        assert_eq!(Ok(Value::Number(2.0)), eval("if(90 min < 2 h, 2, 3)"));
+        assert_eq!(
+            "300 s",
+            q("min(5, 6) * 1 min to s"),
+            "min is both a function and a unit"
+        );
+        assert_eq!(
+            Ok(Value::Number(Rational::new(1, 8).unwrap())),
+            eval_exact("1 ft / 8 ft")
+        );
+
+        let incompatible = |txt: &str| eval(txt).unwrap_err().to_string();
+        assert_eq!("incompatible units m and s", incompatible("1 m + 1 s"));
+        assert_eq!("incompatible units m and 1", incompatible("1 m - 1"));
+        assert_eq!(
+            "incompatible units m s^-1 and m",
+            incompatible("1 km/h to m")
+        );
+        assert_eq!("incompatible units 1 and m", incompatible("2 to m"));
+        assert_eq!("incompatible units kg and m", incompatible("1 kg < 1 m"));
+        assert_eq!(
+            "expected a number, found a quantity",
+            incompatible("sin(1 m)")
+        );
+        assert_eq!(
+            "expected a number, found a quantity",
+            incompatible("2 ^ 1 m")
+        );
+        assert_eq!(
+            "expected a number, found a quantity",
+            incompatible("1 m & 1")
+        );
+        assert_eq!(
+            "(m)^0.5 cannot be represented exactly",
+            incompatible("(1 m)^0.5")
+        );
+
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 9,
+                expected: Expected::Unit,
+                found: Some(String::from("furlong")),
+            })),
+            eval("10 km to furlong")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 5,
+                expected: Expected::Exponent,
+                found: Some(String::from("x")),
+            })),
+            eval("3 m^-x")
+        );
+        assert_eq!(
+            Err(CalcError::Parse(ParseError {
+                pos: 2,
+                expected: Expected::End,
+                found: Some(String::from("furlong")),
+            })),
+            eval("3 furlong")
+        );
+
+        let display = |txt: &str| parse(txt).unwrap().to_string();
+        assert_eq!("3 m s^-1 * 2", display("3 m/s * 2"));
+        assert_eq!("(3 m) ^ 2", display("(3 m)^2"));
+        assert_eq!("(1 km + 2 m to mi) * 2", display("(1 km + 2 m to mi) * 2"));
+        assert_eq!("-(1 km to m)", display("-(1km to m)"));
+        assert_eq!("x to km to m", display("(x to km) to m"));
+        assert_eq!("6 m / 2 s", display("6 m / 2 s"));
+    }
+
+    #[test]
+    fn test_operator_table() {
+        assert_eq!("^", Operator::Power.symbol());
+        assert_eq!(Associativity::Right, Operator::Power.associativity());
+        assert_eq!(Associativity::Left, Operator::Substract.associativity());
+        assert!(Operator::Power.precedence() > UNARY_PRECEDENCE);
+        assert!(Operator::Multiply.precedence() < UNARY_PRECEDENCE);
+        assert_eq!(
+            Operator::Multiply.precedence(),
+            Operator::Modulo.precedence()
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let display = |txt: &str| parse(txt).unwrap().to_string();
+        assert_eq!("2 * (3 + 4) + 5 / 6", display("2*(3+4)+5/6"));
+        assert_eq!("1 + 2", display("((1+2))"));
+        assert_eq!("1 - 2 - 3", display("(1-2)-3"));
+        assert_eq!("1 - (2 - 3)", display("1-(2-3)"));
+        assert_eq!("1 + (2 + 3)", display("1+(2+3)"));
+        assert_eq!("2 ^ 3 ^ 2", display("2^(3^2)"));
+        assert_eq!("(2 ^ 3) ^ 2", display("(2^3)^2"));
+        assert_eq!("-(2 + 3)", display("-(2+3)"));
+        assert_eq!("-2 ^ 2", display("-(2^2)"));
+        assert_eq!("(-2) ^ 2", display("(-2)^2"));
+        assert_eq!("2 ^ -1 * 3", display("2^-1*3"));
+        assert_eq!("2 * -3 - -4 + -0.25", display("2*-3--4+-.25"));
+        assert_eq!("max(1, 2 * x, sqrt(y))", display("max(1,(2*x),sqrt(y))"));
+        assert_eq!("!(x < 1) || y && x != 2", display("!(x<1)||(y&&(x!=2))"));
+        assert_eq!("(x || y) && !z", display("(x||y)&&!z"));
+        assert_eq!("1 < (2 == true)", display("1<(2==true)"));
+        assert_eq!("1 < 2 == true", display("(1<2)==true"));
+        assert_eq!("if(x > 0, -x, 2 * x)", display("if((x>0),-x,2*x)"));
+        assert_eq!(
+            "2 - -3",
+            Node::Binary(
+                Operator::Substract,
+                Box::new(Node::Value(2.0)),
+                Box::new(Node::Value(-3.0))
+            )
+            .to_string()
+        );
+        assert_eq!(
+            "(-3) ^ 2",
+            Node::Binary(
+                Operator::Power,
+                Box::new(Node::Value(-3.0)),
+                Box::new(Node::Value(2.0))
+            )
+            .to_string()
+        );
+    }
+
+    /// xorshift pseudo random generator, so the property tests are reproducible
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    /// generate a random tree of at most the given depth
+    fn random_node(rnd: &mut Random, depth: usize) -> Node {
+        let leaf = depth == 0 || rnd.below(4) == 0;
+        match if leaf { rnd.below(3) } else { 3 + rnd.below(4) } {
+            0 => Node::Value(rnd.below(1000) as f64 / 8.0),
+            1 => Node::Value(-(rnd.below(100) as f64) - 0.5),
+            2 => Node::Variable(String::from(["x", "y"][rnd.below(2) as usize])),
+            3 => Node::SubNode(Box::new(random_node(rnd, depth - 1))),
+            4 => Node::Unary(UnaryOperator::Negate, Box::new(random_node(rnd, depth - 1))),
+            5 => {
+                let name = ["max", "min", "abs", "cos"][rnd.below(4) as usize];
+                let count = if name == "max" || name == "min" {
+                    1 + rnd.below(3)
+                } else {
+                    1
+                };
+                let args = (0..count).map(|_| random_node(rnd, depth - 1)).collect();
+                Node::Call(String::from(name), args)
+            }
+            _ => {
+                let arithmetic = OPERATORS
+                    .iter()
+                    .map(|e| e.1)
+                    .filter(|op| op.is_arithmetic() && !op.is_bitwise())
+                    .collect::<Vec<_>>();
+                let op = arithmetic[rnd.below(arithmetic.len() as u64) as usize];
+                Node::Binary(
+                    op,
+                    Box::new(random_node(rnd, depth - 1)),
+                    Box::new(random_node(rnd, depth - 1)),
+                )
+            }
+        }
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let mut env = Environment::new();
+        env.set("x", 1.5);
+        env.set("y", -2.25);
+        let mut rnd = Random(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let n = random_node(&mut rnd, 6);
+            let txt = n.to_string();
+            let parsed = parse(&txt).unwrap_or_else(|e| panic!("{}: {}", txt, e));
+            let v1 = eval_with(&n, &env).and_then(Value::number).unwrap();
+            let v2 = eval_with(&parsed, &env).and_then(Value::number).unwrap();
+            assert!(
+                v1 == v2 || (v1.is_nan() && v2.is_nan()),
+                "{:?} displayed as {} evaluates to {} instead of {}",
+                n,
+                txt,
+                v2,
+                v1
+            );
+            // display is stable once the redundant sub nodes are gone
+            assert_eq!(txt, parsed.to_string());
+        }
+    }
+
+    #[test]
+    fn test_simplify() {
+        let simplified = |txt: &str| simplify(parse(txt).unwrap()).to_string();
+        assert_eq!(
+            Node::Variable(String::from("x")),
+            simplify(parse("((x))").unwrap())
+        );
+        assert_eq!(
+            Node::Value(89.0 / 6.0),
+            simplify(parse("2*(3+4)+5/6").unwrap())
+        );
+        assert_eq!("6 + x * 1", simplified("2*3+x*1"));
+        assert_eq!("x ^ 3", simplified("x^(1+2)^1"));
+        assert_eq!("-3", simplified("-(1+2)"));
+        // neutral elements are dropped from numbers
+        assert_eq!("1.5 & 1", simplified("((0+(1.5&1))-0)/1^1"));
+        assert_eq!("1.5 & 1", simplified("--(1.5&1)"));
+        assert_eq!("-(1.5 & 1)", simplified("0-(1.5&1)"));
+        assert_eq!("if(x, 1.5 & 1, 2)", simplified("if(x, 1.5&1, 2)*1"));
+        // but not from variables, that may hold a boolean or a quantity
+        assert_eq!("(x + 0) * (1 * y)", simplified("(x+0)*(1*y)"));
+        assert_eq!("--x", simplified("--x"));
+        assert_eq!("0 - x", simplified("0-x"));
+        assert_eq!("x + 1 + 2", simplified("x+1+2"));
+        assert_eq!("2 ^ 3 ^ x", simplified("(2)^((3)^x)"));
+        assert_eq!("max(x * 1, 3, y)", simplified("max(x*1, 1+2, (y))"));
+        assert_eq!("if(x < 3, x * 1, x)", simplified("if(x < 1+2, x*1, (x))"));
+        assert_eq!("!(1 < 2)", simplified("!(1<2)"));
+    }
+
+    #[test]
+    fn test_simplify_round_trip() {
+        let mut env = Environment::new();
+        env.set("x", 0.75);
+        for txt in [
+            "1/0",
+            "0/0",
+            "2^2000",
+            "-(1/0)",
+            "1e308 * 10 + x",
+            "x * (0/0)",
+            "2*(3+4)+5/6",
+        ] {
+            let simplified = simplify(parse(txt).unwrap());
+            let displayed = simplified.to_string();
+            let parsed = parse(&displayed).unwrap();
+            assert_eq!(displayed, parsed.to_string(), "{}", txt);
+            assert_eq!(
+                format!("{:?}", eval_with(&parse(txt).unwrap(), &env)),
+                format!("{:?}", eval_with(&parsed, &env)),
+                "{} simplified to {}",
+                txt,
+                displayed
+            );
+        }
+        assert_eq!("1 / 0", simplify(parse("1/0").unwrap()).to_string());
+        assert_eq!("-(1 / 0)", simplify(parse("-(1/0)").unwrap()).to_string());
+    }
+
+    #[test]
+    fn test_simplify_preserves_errors() {
+        let mut env = Environment::new();
+        env.set("b", Value::Bool(true));
+        env.set("d", eval("3 m").unwrap());
+        for txt in [
+            "true + 0",
+            "0 + b",
+            "b - 0",
+            "0 - b",
+            "b * 1",
+            "1 * b",
+            "b / 1",
+            "b ^ 1",
+            "--b",
+            "3 m + 0",
+            "d - 0",
+            "0 - d",
+            "0 + (1 m) * 2",
+        ] {
+            let n = parse(txt).unwrap();
+            let e = eval_with(&n, &env);
+            assert!(e.is_err(), "{} evaluates to {:?}", txt, e);
+            assert_eq!(e, eval_with(&simplify(n), &env), "{}", txt);
+        }
+    }
+
+    #[test]
+    fn test_simplify_preserves_value() {
+        let mut env = Environment::new();
+        env.set("x", 0.75);
+        env.set("y", -3.0);
+        let mut rnd = Random(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..2000 {
+            let n = random_node(&mut rnd, 6);
+            let v1 = eval_with(&n, &env).and_then(Value::number).unwrap();
+            let s = simplify(n.clone());
+            let v2 = eval_with(&s, &env).and_then(Value::number).unwrap();
+            assert!(
+                v1 == v2 || (v1.is_nan() && v2.is_nan()),
+                "{} simplified to {} evaluates to {} instead of {}",
+                n,
+                s,
+                v2,
+                v1
+            );
+        }
+    }
+
+    #[test]
+    fn test_derive() {
+        let derived = |txt: &str| derive(&parse(txt).unwrap(), "x").unwrap().to_string();
+        assert_eq!("0", derived("3*y+2"));
+        assert_eq!("1", derived("x"));
+        assert_eq!("3", derived("3*x"));
+        assert_eq!("y", derived("x*y"));
+        assert_eq!("2 * x", derived("x^2"));
+        assert_eq!("3 * x ^ 2 + 2", derived("x^3+2*x"));
+        assert_eq!("-sin(x)", derived("cos(x)"));
+        assert_eq!("cos(x) * x + sin(x)", derived("sin(x)*x"));
+        assert_eq!("-1 / x ^ 2", derived("1/x"));
+        assert_eq!("2 * exp(2 * x)", derived("exp(2*x)"));
+        assert_eq!(
+            Err(EvalError::NotDifferentiable(String::from("max"))),
+            derive(&parse("max(x, 2)").unwrap(), "x")
+        );
+        assert_eq!(
+            Err(EvalError::NotDifferentiable(String::from("f"))),
+            derive(&parse("f(x)").unwrap(), "x")
+        );
+        assert_eq!(
+            Err(EvalError::NotDifferentiable(String::from("%"))),
+            derive(&parse("2 % x").unwrap(), "x")
+        );
+        assert_eq!(
+            Ok(Node::Value(0.0)),
+            derive(&parse("f(y) + max(y, 2)").unwrap(), "x")
+        );
+        assert_eq!("if(x > 0, 2 * x, -1)", derived("if(x > 0, x^2, -x)"));
+        assert_eq!("0", derived("if(x > 0, 1, 2)"));
+        assert_eq!(
+            Err(EvalError::NotDifferentiable(String::from("<"))),
+            derive(&parse("x < 1").unwrap(), "x")
+        );
+    }
+
+    #[test]
+    fn test_derive_finite_differences() {
+        let exprs = [
+            "x^3 + 2*x",
+            "sin(x)*x",
+            "x/(1+x^2)",
+            "exp(2*x)/x",
+            "ln(x)*sqrt(x)",
+            "x^x",
+            "cos(x^2)",
+            "-x*tan(x)",
+            "2^x - y*x",
+            "abs(x)*y",
+            "max(y,2)*x",
+            "x % 2",
+            "(x - y)^2 / (x + y)",
+            "round(y) * x",
+            "min(x) - x^-2",
+        ];
+        let h = 1e-6;
+        for txt in exprs.iter() {
+            let n = parse(txt).unwrap();
+            let d = derive(&n, "x").unwrap();
+            for &x in [0.3, 1.1, 2.7].iter() {
+                let at = |x: f64| {
+                    let mut env = Environment::new();
+                    env.set("x", x);
+                    env.set("y", 1.75);
+                    env
+                };
+                let value = |x: f64| eval_with(&n, &at(x)).and_then(Value::number).unwrap();
+                let numeric = (value(x + h) - value(x - h)) / (2.0 * h);
+                let symbolic = eval_with(&d, &at(x)).and_then(Value::number).unwrap();
+                assert!(
+                    (numeric - symbolic).abs() <= 1e-5 * (1.0 + symbolic.abs()),
+                    "d/dx {} = {} gives {} at {}, expected {}",
+                    txt,
+                    d,
+                    symbolic,
+                    x,
+                    numeric
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_eval_exact() {
+        let r = |n, d| Rational::new(n, d).unwrap();
+        assert_eq!(Ok(Value::Number(r(1, 2))), eval_exact("1/3+1/6"));
+        assert_eq!(Ok(Value::Number(r(3, 10))), eval_exact("0.1+0.2"));
+        assert_ne!(Ok(Value::Number(0.3)), eval("0.1+0.2"));
+        assert_eq!(
+            Ok(Value::Number(r(7000, 1))),
+            eval_exact("2 * (3 + (4 * 5 + (6 * 7) * 8) - 9) * 10")
+        );
+        assert_eq!(Ok(Value::Number(r(-9, 4))), eval_exact("2*-3--4+-.25"));
+        assert_eq!(
+            Ok(Value::Number(r(1, 1))),
+            eval_exact("9007199254740993 - 9007199254740992")
+        );
+        assert_eq!(
+            Ok(Value::Number(r(1, 1))),
+            eval_exact("1.0000000000000000001e19 - 10^19")
+        );
+        assert_eq!(
+            Ok(Value::Number(0.0)),
+            eval("9007199254740993 - 9007199254740992")
+        );
+        assert_eq!(
+            "9007199254740993 + 0.1",
+            parse("9007199254740993 + 0.1").unwrap().to_string()
+        );
+        assert_eq!(Ok(Value::Number(r(1, 1024))), eval_exact("2^-10"));
+        assert_eq!(Ok(Value::Number(r(3, 1))), eval_exact("max(1/3, 3)"));
+        assert_eq!(
+            Ok(Value::Number(r(
+                1_000_000_000_000_000_001,
+                1_000_000_000_000_000_000
+            ))),
+            eval_exact("1 + 1/10^18")
+        );
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::DivisionByZero)),
+            eval_exact("1/(1/2-0.5)")
+        );
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::Overflow)),
+            eval_exact("10^40")
+        );
+        assert_eq!(
+            "0.1 + 0.2 = 3/10",
+            format!("0.1 + 0.2 = {}", eval_exact("0.1 + 0.2").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_eval_integer() {
+        // literals above 2^53 are not rounded through floats
+        assert_eq!(
+            Ok(Value::Number(9007199254740993)),
+            eval_integer("9007199254740993")
+        );
+        assert_eq!(
+            Ok(Value::Number(i64::MAX)),
+            eval_integer("9_223_372_036_854_775_807")
+        );
+        assert_eq!(
+            Ok(Value::Number(-0x7fff_ffff_ffff_ffff)),
+            eval_integer("-0x7fff_ffff_ffff_ffff")
+        );
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::NotRepresentable(String::from(
+                "9223372036854775808"
+            )))),
+            eval_integer("9223372036854775808")
+        );
+        assert_eq!(
+            Ok(Value::Number(7000)),
+            eval_integer("2 * (3 + (4 * 5 + (6 * 7) * 8) - 9) * 10")
+        );
+        assert_eq!(Ok(Value::Number(3)), eval_integer("7 / 2"));
+        assert_eq!(Ok(Value::Number(i64::MAX)), eval_integer("2^62 - 1 + 2^62"));
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::Overflow)),
+            eval_integer("2^63")
+        );
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::DivisionByZero)),
+            eval_integer("1 / (2 - 2)")
+        );
+        assert_eq!(
+            Err(CalcError::Eval(EvalError::NotRepresentable(String::from(
+                "0.5"
+            )))),
+            eval_integer("1 + 0.5")
+        );
+        let mut env = Environment::new();
+        env.set("n", 20.0);
+        assert_eq!(
+            Ok(Value::Number(1048576_i64)),
+            eval_as(&parse("2^n").unwrap(), &env)
+        );
     }
 
     #[test]
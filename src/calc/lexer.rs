@@ -0,0 +1,267 @@
+//! Split the input into tokens, keeping track of where each token comes from
+
+use super::{Operator, OPERATORS};
+use std::ops::Range;
+
+/// The kind of a token
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// an unsigned number literal
+    Number(f64),
+    /// a variable, function or keyword name
+    Identifier(String),
+    /// a binary operator, `-` also being the negation
+    Operator(Operator),
+    /// `!`
+    Not,
+    /// `=`, between a function definition and its body
+    Assign,
+    OpenParenthesis,
+    CloseParenthesis,
+    Comma,
+    /// a malformed literal or an unknown character, reported by the parser
+    Invalid,
+    /// the end of the input, always the last token
+    End,
+}
+
+/// A token, with its text and its span in characters in the input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Range<usize>,
+}
+
+/// split a string into tokens, skipping whitespace. Never fails: bad input gives `Invalid` tokens.
+pub fn tokenize(txt: &str) -> Vec<Token> {
+    let chars = txt.chars().collect::<Vec<_>>();
+    let mut tokens = vec![];
+    let mut pos = 0;
+    while pos < chars.len() {
+        if chars[pos].is_whitespace() {
+            pos += 1;
+            continue;
+        }
+        let (end, kind) = lex_token(&chars, pos);
+        tokens.push(Token {
+            kind,
+            text: chars[pos..end].iter().collect(),
+            span: pos..end,
+        });
+        pos = end;
+    }
+    tokens.push(Token {
+        kind: TokenKind::End,
+        text: String::new(),
+        span: pos..pos,
+    });
+    tokens
+}
+
+/// lex the token starting at the given position, which is not whitespace
+fn lex_token(chars: &[char], pos: usize) -> (usize, TokenKind) {
+    let c = chars[pos];
+    if c.is_alphabetic() || c == '_' {
+        let end = scan(chars, pos + 1, |c| c.is_alphanumeric() || c == '_');
+        return (end, TokenKind::Identifier(chars[pos..end].iter().collect()));
+    }
+    if c.is_ascii_digit() || c == '.' {
+        return lex_number(chars, pos);
+    }
+    let operator = OPERATORS.iter().find_map(|(symbol, op, _, _)| {
+        let end = pos + symbol.chars().count();
+        if end <= chars.len() && chars[pos..end].iter().copied().eq(symbol.chars()) {
+            Some((end, TokenKind::Operator(*op)))
+        } else {
+            None
+        }
+    });
+    operator.unwrap_or_else(|| {
+        let kind = match c {
+            '!' => TokenKind::Not,
+            '=' => TokenKind::Assign,
+            '(' => TokenKind::OpenParenthesis,
+            ')' => TokenKind::CloseParenthesis,
+            ',' => TokenKind::Comma,
+            _ => TokenKind::Invalid,
+        };
+        (pos + 1, kind)
+    })
+}
+
+/// lex an unsigned number: a hexadecimal (`0x`), binary (`0b`) or octal (`0o`) integer,
+/// or a decimal with an optional exponent. Digits can be separated by underscores.
+fn lex_number(chars: &[char], pos: usize) -> (usize, TokenKind) {
+    let radix = match (chars.get(pos), chars.get(pos + 1)) {
+        (Some('0'), Some('x')) => 16,
+        (Some('0'), Some('b')) => 2,
+        (Some('0'), Some('o')) => 8,
+        _ => 10,
+    };
+    let (end, v) = if radix == 10 {
+        let mut end = scan(chars, pos, |c| c.is_ascii_digit() || c == '_' || c == '.');
+        // only take the exponent if it has digits, so `1e` is a number followed by a name
+        if let Some('e') | Some('E') = chars.get(end) {
+            let mut exponent = end + 1;
+            if let Some('+') | Some('-') = chars.get(exponent) {
+                exponent += 1;
+            }
+            if chars.get(exponent).is_some_and(char::is_ascii_digit) {
+                end = scan(chars, exponent, |c| c.is_ascii_digit() || c == '_');
+            }
+        }
+        let txt = chars[pos..end].iter().collect::<String>();
+        let v = if separators_between_digits(&chars[pos..end], 10) {
+            txt.replace('_', "").parse::<f64>().ok()
+        } else {
+            None
+        };
+        (end, v)
+    } else {
+        // scan all alphanumerics so `0b102` is reported as a whole
+        let end = scan(chars, pos + 2, |c| c.is_alphanumeric() || c == '_');
+        let digits = &chars[pos + 2..end];
+        let v = if separators_between_digits(digits, radix) {
+            let txt = digits.iter().filter(|c| **c != '_').collect::<String>();
+            u64::from_str_radix(&txt, radix).ok().map(|v| v as f64)
+        } else {
+            None
+        };
+        (end, v)
+    };
+    (end, v.map_or(TokenKind::Invalid, TokenKind::Number))
+}
+
+/// the position after the characters matching the predicate
+fn scan<F: Fn(char) -> bool>(chars: &[char], pos: usize, f: F) -> usize {
+    chars[pos..]
+        .iter()
+        .position(|c| !f(*c))
+        .map_or(chars.len(), |l| pos + l)
+}
+
+/// are all the underscores between two digits of the given radix
+fn separators_between_digits(chars: &[char], radix: u32) -> bool {
+    let digit = |ix: usize| chars.get(ix).is_some_and(|c| c.is_digit(radix));
+    chars
+        .iter()
+        .enumerate()
+        .all(|(ix, c)| *c != '_' || (ix > 0 && digit(ix - 1) && digit(ix + 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(txt: &str) -> Vec<TokenKind> {
+        tokenize(txt).into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            vec![
+                Token {
+                    kind: TokenKind::Identifier(String::from("max")),
+                    text: String::from("max"),
+                    span: 0..3
+                },
+                Token {
+                    kind: TokenKind::OpenParenthesis,
+                    text: String::from("("),
+                    span: 3..4
+                },
+                Token {
+                    kind: TokenKind::Number(255.0),
+                    text: String::from("0xFF"),
+                    span: 4..8
+                },
+                Token {
+                    kind: TokenKind::Comma,
+                    text: String::from(","),
+                    span: 8..9
+                },
+                Token {
+                    kind: TokenKind::Number(1000.0),
+                    text: String::from("1_000"),
+                    span: 10..15
+                },
+                Token {
+                    kind: TokenKind::CloseParenthesis,
+                    text: String::from(")"),
+                    span: 15..16
+                },
+                Token {
+                    kind: TokenKind::End,
+                    text: String::new(),
+                    span: 17..17
+                },
+            ],
+            tokenize("max(0xFF, 1_000) ")
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        use TokenKind::Operator as Op;
+        assert_eq!(
+            vec![
+                Op(Operator::LessEqual),
+                Op(Operator::ShiftLeft),
+                Op(Operator::Less),
+                Op(Operator::Power),
+                Op(Operator::BitXor),
+                Op(Operator::NotEqual),
+                TokenKind::Not,
+                Op(Operator::And),
+                Op(Operator::BitAnd),
+                Op(Operator::Or),
+                Op(Operator::Substract),
+                TokenKind::End,
+            ],
+            kinds("<=<< < ^ ^^!= ! && & || -")
+        );
+        // no implicit joining across whitespace
+        assert_eq!(
+            vec![
+                Op(Operator::Less),
+                TokenKind::Assign,
+                Op(Operator::And),
+                Op(Operator::BitAnd),
+                TokenKind::End
+            ],
+            kinds("< = &&&")
+        );
+    }
+
+    #[test]
+    fn test_numbers_and_names() {
+        assert_eq!(
+            vec![
+                TokenKind::Number(1.0),
+                TokenKind::Number(2.0),
+                TokenKind::Number(0.001),
+                TokenKind::Identifier(String::from("x_1")),
+                TokenKind::End
+            ],
+            kinds("1 2\t1e-3 x_1")
+        );
+        assert_eq!(
+            vec![
+                TokenKind::Number(1.0),
+                TokenKind::Identifier(String::from("e")),
+                TokenKind::Number(12.0),
+                TokenKind::Identifier(String::from("abc")),
+                TokenKind::End
+            ],
+            kinds("1e 12abc")
+        );
+        for txt in ["0b102", "0x", "1__0", "1_", "1.2.3", "#"].iter() {
+            let tokens = tokenize(txt);
+            assert_eq!(TokenKind::Invalid, tokens[0].kind, "{}", txt);
+            assert_eq!(*txt, tokens[0].text);
+            assert_eq!(2, tokens.len());
+        }
+    }
+}
@@ -0,0 +1,521 @@
+//! Number types the calculator can evaluate with: floats, checked integers and exact rationals
+
+use super::{EvalError, Operator, UnaryOperator};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A number type expressions can be evaluated with
+pub trait Number: Sized + Clone + PartialOrd + fmt::Debug + fmt::Display {
+    /// convert a float, used for exact literals, variables and function results
+    fn from_f64(v: f64) -> Result<Self, EvalError>;
+
+    /// convert a literal the float cannot represent exactly, given with its source text
+    fn from_literal(v: f64, _txt: &str) -> Result<Self, EvalError> {
+        Self::from_f64(v)
+    }
+
+    /// convert to a float, used for function arguments
+    fn to_f64(&self) -> f64;
+
+    /// apply an arithmetic unary operator
+    fn unary(op: UnaryOperator, a: &Self) -> Result<Self, EvalError>;
+
+    /// apply an arithmetic binary operator
+    fn binary(op: Operator, a: &Self, b: &Self) -> Result<Self, EvalError>;
+}
+
+/// Plain floating point arithmetic, never failing
+impl Number for f64 {
+    fn from_f64(v: f64) -> Result<Self, EvalError> {
+        Ok(v)
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+
+    fn unary(op: UnaryOperator, a: &Self) -> Result<Self, EvalError> {
+        Ok(op.apply(*a))
+    }
+
+    fn binary(op: Operator, a: &Self, b: &Self) -> Result<Self, EvalError> {
+        if op.is_bitwise() {
+            let integer =
+                |v: f64| i64::from_f64(v).map_err(|_| EvalError::NotInteger(v.to_string()));
+            return bitwise(op, integer(*a)?, integer(*b)?).map(|r| r as f64);
+        }
+        Ok(op.apply(*a, *b))
+    }
+}
+
+/// Integer arithmetic, failing on overflow instead of wrapping.
+/// Division truncates towards zero, like the Rust operator.
+impl Number for i64 {
+    fn from_f64(v: f64) -> Result<Self, EvalError> {
+        if v.fract() == 0.0 && v >= i64::MIN as f64 && v < i64::MAX as f64 {
+            Ok(v as i64)
+        } else {
+            Err(EvalError::NotRepresentable(v.to_string()))
+        }
+    }
+
+    fn from_literal(_v: f64, txt: &str) -> Result<Self, EvalError> {
+        let r = Rational::from_literal_text(txt)
+            .map_err(|_| EvalError::NotRepresentable(txt.to_string()))?;
+        if r.is_integer() {
+            i64::try_from(r.numerator).map_err(|_| EvalError::NotRepresentable(txt.to_string()))
+        } else {
+            Err(EvalError::NotRepresentable(txt.to_string()))
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+
+    fn unary(op: UnaryOperator, a: &Self) -> Result<Self, EvalError> {
+        match op {
+            UnaryOperator::Negate => a.checked_neg().ok_or(EvalError::Overflow),
+            _ => unreachable!("'{}' is not arithmetic", op.symbol()),
+        }
+    }
+
+    fn binary(op: Operator, a: &Self, b: &Self) -> Result<Self, EvalError> {
+        let (a, b) = (*a, *b);
+        if b == 0 && (op == Operator::Divide || op == Operator::Modulo) {
+            return Err(EvalError::DivisionByZero);
+        }
+        let r = match op {
+            Operator::Add => a.checked_add(b),
+            Operator::Substract => a.checked_sub(b),
+            Operator::Multiply => a.checked_mul(b),
+            Operator::Divide => a.checked_div(b),
+            Operator::Modulo => a.checked_rem(b),
+            Operator::Power if b < 0 => {
+                return Err(EvalError::NotRepresentable(format!("{}^{}", a, b)))
+            }
+            Operator::Power => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+            _ if op.is_bitwise() => return bitwise(op, a, b),
+            _ => unreachable!("'{}' is not arithmetic", op.symbol()),
+        };
+        r.ok_or(EvalError::Overflow)
+    }
+}
+
+/// apply a bitwise operator to two integers, shifts failing if bits are lost
+fn bitwise(op: Operator, a: i64, b: i64) -> Result<i64, EvalError> {
+    let shift = || {
+        u32::try_from(b)
+            .ok()
+            .filter(|b| *b < i64::BITS)
+            .ok_or(EvalError::Overflow)
+    };
+    match op {
+        Operator::BitAnd => Ok(a & b),
+        Operator::BitOr => Ok(a | b),
+        Operator::BitXor => Ok(a ^ b),
+        Operator::ShiftLeft => {
+            let s = shift()?;
+            Some(a << s)
+                .filter(|r| r >> s == a)
+                .ok_or(EvalError::Overflow)
+        }
+        Operator::ShiftRight => Ok(a >> shift()?),
+        _ => unreachable!("'{}' is not bitwise", op.symbol()),
+    }
+}
+
+/// An exact fraction, always kept reduced with a positive denominator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rational {
+    numerator: i128,
+    denominator: i128,
+}
+
+impl Rational {
+    /// new reduced fraction
+    pub fn new(numerator: i128, denominator: i128) -> Result<Rational, EvalError> {
+        if denominator == 0 {
+            return Err(EvalError::DivisionByZero);
+        }
+        let g = i128::try_from(gcd(numerator.unsigned_abs(), denominator.unsigned_abs()))
+            .map_err(|_| EvalError::Overflow)?;
+        let (mut numerator, mut denominator) = (numerator / g, denominator / g);
+        if denominator < 0 {
+            numerator = numerator.checked_neg().ok_or(EvalError::Overflow)?;
+            denominator = denominator.checked_neg().ok_or(EvalError::Overflow)?;
+        }
+        Ok(Rational {
+            numerator,
+            denominator,
+        })
+    }
+
+    /// new integral fraction
+    pub fn from_integer(n: i128) -> Rational {
+        Rational {
+            numerator: n,
+            denominator: 1,
+        }
+    }
+
+    /// the numerator, carrying the sign
+    pub fn numerator(&self) -> i128 {
+        self.numerator
+    }
+
+    /// the denominator, always positive
+    pub fn denominator(&self) -> i128 {
+        self.denominator
+    }
+
+    /// is the fraction an integer
+    pub fn is_integer(&self) -> bool {
+        self.denominator == 1
+    }
+
+    /// the exact value of a number literal as lexed: a hexadecimal (`0x`), binary (`0b`)
+    /// or octal (`0o`) integer, or a decimal with an optional exponent, possibly with underscores
+    pub fn from_literal_text(txt: &str) -> Result<Rational, EvalError> {
+        let txt = txt.replace('_', "");
+        let radix = match txt.get(..2) {
+            Some("0x") => 16,
+            Some("0b") => 2,
+            Some("0o") => 8,
+            _ => 10,
+        };
+        if radix != 10 {
+            return i128::from_str_radix(&txt[2..], radix)
+                .map(Rational::from_integer)
+                .map_err(|_| EvalError::Overflow);
+        }
+        let (mantissa, exponent) = match txt.find(['e', 'E']) {
+            Some(ix) => (
+                &txt[..ix],
+                txt[ix + 1..]
+                    .parse::<i32>()
+                    .map_err(|_| EvalError::Overflow)?,
+            ),
+            None => (&txt[..], 0),
+        };
+        let (int, frac) = match mantissa.find('.') {
+            Some(ix) => (&mantissa[..ix], &mantissa[ix + 1..]),
+            None => (mantissa, ""),
+        };
+        let mut numerator: i128 = 0;
+        for c in int.chars().chain(frac.chars()) {
+            let digit = c
+                .to_digit(10)
+                .ok_or_else(|| EvalError::NotRepresentable(txt.clone()))?;
+            numerator = numerator
+                .checked_mul(10)
+                .and_then(|n| n.checked_add(i128::from(digit)))
+                .ok_or(EvalError::Overflow)?;
+        }
+        let scale = i64::from(exponent) - frac.len() as i64;
+        let power = u32::try_from(scale.abs())
+            .ok()
+            .and_then(|p| 10_i128.checked_pow(p))
+            .ok_or(EvalError::Overflow)?;
+        if scale >= 0 {
+            let n = numerator.checked_mul(power).ok_or(EvalError::Overflow)?;
+            Ok(Rational::from_integer(n))
+        } else {
+            Rational::new(numerator, power)
+        }
+    }
+
+    /// raise to an integral power, by repeated squaring
+    fn pow(&self, exponent: i128) -> Result<Rational, EvalError> {
+        let base = if exponent < 0 {
+            Rational::new(self.denominator, self.numerator)?
+        } else {
+            *self
+        };
+        let mut e = exponent.unsigned_abs();
+        let (mut n, mut d) = (base.numerator, base.denominator);
+        let (mut rn, mut rd): (i128, i128) = (1, 1);
+        while e > 0 {
+            if e & 1 == 1 {
+                rn = rn.checked_mul(n).ok_or(EvalError::Overflow)?;
+                rd = rd.checked_mul(d).ok_or(EvalError::Overflow)?;
+            }
+            e >>= 1;
+            if e > 0 {
+                n = n.checked_mul(n).ok_or(EvalError::Overflow)?;
+                d = d.checked_mul(d).ok_or(EvalError::Overflow)?;
+            }
+        }
+        Rational::new(rn, rd)
+    }
+}
+
+/// greatest common divisor, one for two zeros so it can always be divided by
+fn gcd(a: u128, b: u128) -> u128 {
+    let (mut a, mut b) = (a, b);
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    if a == 0 {
+        1
+    } else {
+        a
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
+/// Exact comparison, without the overflows cross multiplying could cause
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // compare the continued fraction expansions term by term
+        let (mut a, mut b) = (self.numerator, self.denominator);
+        let (mut c, mut d) = (other.numerator, other.denominator);
+        let mut reversed = false;
+        loop {
+            let (q1, q2) = (a.div_euclid(b), c.div_euclid(d));
+            let (r1, r2) = (a.rem_euclid(b), c.rem_euclid(d));
+            let o = q1.cmp(&q2).then_with(|| (r1 != 0).cmp(&(r2 != 0)));
+            if o != Ordering::Equal || r1 == 0 {
+                return if reversed { o.reverse() } else { o };
+            }
+            // r1/b < r2/d if and only if b/r1 > d/r2
+            a = b;
+            b = r1;
+            c = d;
+            d = r2;
+            reversed = !reversed;
+        }
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Exact arithmetic, failing on overflow.
+/// Floats are converted from their shortest decimal representation, so the literal `0.1` is exactly 1/10.
+impl Number for Rational {
+    fn from_f64(v: f64) -> Result<Self, EvalError> {
+        if !v.is_finite() {
+            return Err(EvalError::NotRepresentable(v.to_string()));
+        }
+        // the display of a float never uses an exponent
+        let txt = v.abs().to_string();
+        let (int, frac) = match txt.find('.') {
+            Some(ix) => (&txt[..ix], &txt[ix + 1..]),
+            None => (&txt[..], ""),
+        };
+        let mut numerator: i128 = 0;
+        for c in int.chars().chain(frac.chars()) {
+            numerator = numerator
+                .checked_mul(10)
+                .and_then(|n| n.checked_add(i128::from(c.to_digit(10).unwrap())))
+                .ok_or(EvalError::Overflow)?;
+        }
+        let denominator = u32::try_from(frac.len())
+            .ok()
+            .and_then(|l| 10_i128.checked_pow(l))
+            .ok_or(EvalError::Overflow)?;
+        let r = Rational::new(numerator, denominator)?;
+        if v < 0.0 {
+            Rational::unary(UnaryOperator::Negate, &r)
+        } else {
+            Ok(r)
+        }
+    }
+
+    fn from_literal(_v: f64, txt: &str) -> Result<Self, EvalError> {
+        Rational::from_literal_text(txt)
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+
+    fn unary(op: UnaryOperator, a: &Self) -> Result<Self, EvalError> {
+        match op {
+            UnaryOperator::Negate => Ok(Rational {
+                numerator: a.numerator.checked_neg().ok_or(EvalError::Overflow)?,
+                denominator: a.denominator,
+            }),
+            _ => unreachable!("'{}' is not arithmetic", op.symbol()),
+        }
+    }
+
+    fn binary(op: Operator, a: &Self, b: &Self) -> Result<Self, EvalError> {
+        let mul = |x: i128, y: i128| x.checked_mul(y).ok_or(EvalError::Overflow);
+        match op {
+            Operator::Add | Operator::Substract => {
+                let n1 = mul(a.numerator, b.denominator)?;
+                let n2 = mul(b.numerator, a.denominator)?;
+                let n = if op == Operator::Add {
+                    n1.checked_add(n2)
+                } else {
+                    n1.checked_sub(n2)
+                };
+                Rational::new(
+                    n.ok_or(EvalError::Overflow)?,
+                    mul(a.denominator, b.denominator)?,
+                )
+            }
+            Operator::Multiply => Rational::new(
+                mul(a.numerator, b.numerator)?,
+                mul(a.denominator, b.denominator)?,
+            ),
+            Operator::Divide => Rational::new(
+                mul(a.numerator, b.denominator)?,
+                mul(a.denominator, b.numerator)?,
+            ),
+            Operator::Modulo => {
+                // a - b * trunc(a / b)
+                let q = Rational::binary(Operator::Divide, a, b)?;
+                let t = Rational::from_integer(q.numerator / q.denominator);
+                Rational::binary(
+                    Operator::Substract,
+                    a,
+                    &Rational::binary(Operator::Multiply, b, &t)?,
+                )
+            }
+            Operator::Power if b.is_integer() => a.pow(b.numerator),
+            Operator::Power => Err(EvalError::NotRepresentable(format!("{}^({})", a, b))),
+            _ if op.is_bitwise() => {
+                let integer = |r: &Rational| {
+                    if r.is_integer() {
+                        i64::try_from(r.numerator).map_err(|_| EvalError::Overflow)
+                    } else {
+                        Err(EvalError::NotInteger(r.to_string()))
+                    }
+                };
+                bitwise(op, integer(a)?, integer(b)?).map(|r| Rational::from_integer(r.into()))
+            }
+            _ => unreachable!("'{}' is not arithmetic", op.symbol()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r(n: i128, d: i128) -> Rational {
+        Rational::new(n, d).unwrap()
+    }
+
+    #[test]
+    fn test_rational() {
+        assert_eq!(r(1, 2), r(2, 4));
+        assert_eq!(r(-1, 2), r(1, -2));
+        assert_eq!((-1, 2), (r(3, -6).numerator(), r(3, -6).denominator()));
+        assert_eq!(Err(EvalError::DivisionByZero), Rational::new(1, 0));
+        assert_eq!(r(0, 1), r(0, -5));
+        assert_eq!("1/2", r(3, 6).to_string());
+        assert_eq!("-3", r(-6, 2).to_string());
+        assert!(r(4, 2).is_integer());
+        assert!(r(1, 3) < r(1, 2));
+        assert!(r(-1, 2) < r(-1, 3));
+        assert!(r(5, 3) > r(3, 2));
+        assert!(r(i128::MAX, 3) > r(i128::MAX - 1, 3));
+        assert!(r(i128::MAX - 2, i128::MAX) < r(i128::MAX - 1, i128::MAX));
+        assert_eq!(Ordering::Equal, r(2, 4).cmp(&r(1, 2)));
+
+        let b = |op, a, b| Rational::binary(op, &a, &b);
+        assert_eq!(Ok(r(1, 2)), b(Operator::Add, r(1, 3), r(1, 6)));
+        assert_eq!(Ok(r(1, 6)), b(Operator::Substract, r(1, 3), r(1, 6)));
+        assert_eq!(Ok(r(1, 18)), b(Operator::Multiply, r(1, 3), r(1, 6)));
+        assert_eq!(Ok(r(2, 1)), b(Operator::Divide, r(1, 3), r(1, 6)));
+        assert_eq!(Ok(r(1, 6)), b(Operator::Modulo, r(1, 2), r(1, 3)));
+        assert_eq!(Ok(r(-1, 6)), b(Operator::Modulo, r(-1, 2), r(1, 3)));
+        assert_eq!(Ok(r(8, 27)), b(Operator::Power, r(2, 3), r(3, 1)));
+        assert_eq!(Ok(r(9, 4)), b(Operator::Power, r(2, 3), r(-2, 1)));
+        assert_eq!(Ok(r(1, 1)), b(Operator::Power, r(2, 3), r(0, 1)));
+        assert_eq!(
+            Err(EvalError::DivisionByZero),
+            b(Operator::Divide, r(1, 3), r(0, 1))
+        );
+        assert_eq!(
+            Err(EvalError::DivisionByZero),
+            b(Operator::Power, r(0, 1), r(-1, 1))
+        );
+        assert_eq!(
+            Err(EvalError::NotRepresentable(String::from("2^(1/2)"))),
+            b(Operator::Power, r(2, 1), r(1, 2))
+        );
+        assert_eq!(
+            Err(EvalError::Overflow),
+            b(Operator::Power, r(10, 1), r(40, 1))
+        );
+    }
+
+    #[test]
+    fn test_rational_from_f64() {
+        assert_eq!(Ok(r(1, 10)), Rational::from_f64(0.1));
+        assert_eq!(Ok(r(-5, 4)), Rational::from_f64(-1.25));
+        assert_eq!(Ok(r(42, 1)), Rational::from_f64(42.0));
+        assert_eq!(Ok(r(0, 1)), Rational::from_f64(-0.0));
+        assert_eq!(
+            Err(EvalError::NotRepresentable(String::from("NaN"))),
+            Rational::from_f64(f64::NAN)
+        );
+        assert_eq!(Err(EvalError::Overflow), Rational::from_f64(1e60));
+        assert_eq!(0.375, r(3, 8).to_f64());
+    }
+
+    #[test]
+    fn test_bitwise() {
+        assert_eq!(Ok(-4), bitwise(Operator::ShiftLeft, -1, 2));
+        assert_eq!(Ok(i64::MIN), bitwise(Operator::ShiftLeft, i64::MIN >> 3, 3));
+        assert_eq!(
+            Err(EvalError::Overflow),
+            bitwise(Operator::ShiftLeft, 1, 63)
+        );
+        assert_eq!(Ok(-1), bitwise(Operator::ShiftRight, -1, 63));
+        assert_eq!(Ok(7), i64::binary(Operator::BitXor, &5, &2));
+        assert_eq!(Ok(12.0), f64::binary(Operator::BitAnd, &12.0, &-4.0));
+        assert_eq!(
+            Err(EvalError::NotInteger(String::from("NaN"))),
+            f64::binary(Operator::BitOr, &f64::NAN, &1.0)
+        );
+    }
+
+    #[test]
+    fn test_integer() {
+        assert_eq!(Ok(3), i64::from_f64(3.0));
+        assert_eq!(
+            Err(EvalError::NotRepresentable(String::from("2.5"))),
+            i64::from_f64(2.5)
+        );
+        assert_eq!(Ok(3), i64::binary(Operator::Divide, &7, &2));
+        assert_eq!(Ok(-1), i64::binary(Operator::Modulo, &-7, &3));
+        assert_eq!(Ok(1024), i64::binary(Operator::Power, &2, &10));
+        assert_eq!(
+            Err(EvalError::Overflow),
+            i64::binary(Operator::Multiply, &i64::MAX, &2)
+        );
+        assert_eq!(
+            Err(EvalError::Overflow),
+            i64::unary(UnaryOperator::Negate, &i64::MIN)
+        );
+        assert_eq!(
+            Err(EvalError::DivisionByZero),
+            i64::binary(Operator::Modulo, &1, &0)
+        );
+        assert_eq!(
+            Err(EvalError::NotRepresentable(String::from("2^-1"))),
+            i64::binary(Operator::Power, &2, &-1)
+        );
+    }
+}
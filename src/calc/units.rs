@@ -0,0 +1,284 @@
+//! Units of measure: physical dimensions, known units and quantities
+
+use std::fmt;
+
+/// The SI base units, in the order of the dimension exponents
+const BASE_UNITS: [&str; 7] = ["m", "kg", "s", "A", "K", "mol", "cd"];
+
+/// The known units: name, value in SI base units, and exponents of
+/// length, mass, time, current, temperature, amount of substance and luminous intensity
+const UNITS: &[(&str, f64, [i32; 7])] = &[
+    ("m", 1.0, [1, 0, 0, 0, 0, 0, 0]),
+    ("km", 1000.0, [1, 0, 0, 0, 0, 0, 0]),
+    ("cm", 0.01, [1, 0, 0, 0, 0, 0, 0]),
+    ("mm", 0.001, [1, 0, 0, 0, 0, 0, 0]),
+    ("mi", 1609.344, [1, 0, 0, 0, 0, 0, 0]),
+    ("yd", 0.9144, [1, 0, 0, 0, 0, 0, 0]),
+    ("ft", 0.3048, [1, 0, 0, 0, 0, 0, 0]),
+    ("in", 0.0254, [1, 0, 0, 0, 0, 0, 0]),
+    ("kg", 1.0, [0, 1, 0, 0, 0, 0, 0]),
+    ("g", 0.001, [0, 1, 0, 0, 0, 0, 0]),
+    ("t", 1000.0, [0, 1, 0, 0, 0, 0, 0]),
+    ("lb", 0.453_592_37, [0, 1, 0, 0, 0, 0, 0]),
+    ("s", 1.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("ms", 0.001, [0, 0, 1, 0, 0, 0, 0]),
+    ("min", 60.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("h", 3600.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("day", 86400.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("A", 1.0, [0, 0, 0, 1, 0, 0, 0]),
+    ("K", 1.0, [0, 0, 0, 0, 1, 0, 0]),
+    ("mol", 1.0, [0, 0, 0, 0, 0, 1, 0]),
+    ("cd", 1.0, [0, 0, 0, 0, 0, 0, 1]),
+    ("L", 0.001, [3, 0, 0, 0, 0, 0, 0]),
+    ("Hz", 1.0, [0, 0, -1, 0, 0, 0, 0]),
+    ("N", 1.0, [1, 1, -2, 0, 0, 0, 0]),
+    ("Pa", 1.0, [-1, 1, -2, 0, 0, 0, 0]),
+    ("J", 1.0, [2, 1, -2, 0, 0, 0, 0]),
+    ("kWh", 3.6e6, [2, 1, -2, 0, 0, 0, 0]),
+    ("W", 1.0, [2, 1, -3, 0, 0, 0, 0]),
+    ("C", 1.0, [0, 0, 1, 1, 0, 0, 0]),
+    ("V", 1.0, [2, 1, -3, -1, 0, 0, 0]),
+];
+
+/// A physical dimension, as exponents of the SI base units
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Dimension([i32; 7]);
+
+impl Dimension {
+    /// the dimension of plain numbers
+    pub const NONE: Dimension = Dimension([0; 7]);
+
+    /// is this the dimension of plain numbers
+    pub fn is_none(&self) -> bool {
+        *self == Dimension::NONE
+    }
+
+    /// the dimension raised to a power, if all the exponents stay integers
+    pub fn pow(self, p: f64) -> Option<Dimension> {
+        let mut exponents = [0; 7];
+        for (e, d) in exponents.iter_mut().zip(self.0.iter()) {
+            let v = f64::from(*d) * p;
+            if v.fract() != 0.0 || v.abs() > f64::from(i32::MAX) {
+                return None;
+            }
+            *e = v as i32;
+        }
+        Some(Dimension(exponents))
+    }
+
+    /// the dimension of a product, if the exponents don't overflow
+    pub fn checked_mul(self, other: Dimension) -> Option<Dimension> {
+        self.combine(other, 1)
+    }
+
+    /// the dimension of a quotient, if the exponents don't overflow
+    pub fn checked_div(self, other: Dimension) -> Option<Dimension> {
+        self.combine(other, -1)
+    }
+
+    /// add the exponents of the other dimension, multiplied by the sign
+    fn combine(self, other: Dimension, sign: i32) -> Option<Dimension> {
+        let mut exponents = self.0;
+        for (e, o) in exponents.iter_mut().zip(other.0.iter()) {
+            *e = e.checked_add(sign.checked_mul(*o)?)?;
+        }
+        Some(Dimension(exponents))
+    }
+}
+
+/// Display the dimension in SI base units, like `m kg s^-2`, or `1` for plain numbers
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_none() {
+            return write!(f, "1");
+        }
+        let factors = BASE_UNITS
+            .iter()
+            .zip(self.0.iter())
+            .filter(|(_, e)| **e != 0)
+            .map(|(u, e)| factor(u, *e))
+            .collect::<Vec<_>>();
+        write!(f, "{}", factors.join(" "))
+    }
+}
+
+/// a unit name with its exponent
+fn factor(name: &str, exponent: i32) -> String {
+    if exponent == 1 {
+        name.to_string()
+    } else {
+        format!("{}^{}", name, exponent)
+    }
+}
+
+/// A unit: its name, and its size and dimension in SI base units
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    name: String,
+    factor: f64,
+    dimension: Dimension,
+}
+
+impl Unit {
+    /// look up a known unit by name
+    pub fn named(name: &str) -> Option<Unit> {
+        UNITS
+            .iter()
+            .find(|u| u.0 == name)
+            .map(|(name, factor, d)| Unit {
+                name: name.to_string(),
+                factor: *factor,
+                dimension: Dimension(*d),
+            })
+    }
+
+    /// the SI unit of a dimension
+    pub fn base(dimension: Dimension) -> Unit {
+        Unit {
+            name: dimension.to_string(),
+            factor: 1.0,
+            dimension,
+        }
+    }
+
+    /// the product of units raised to powers, like `km h^-1`, if the exponents don't overflow
+    pub fn compound(factors: &[(Unit, i32)]) -> Option<Unit> {
+        // merge repeated units, keeping the order they first appear in
+        let mut merged: Vec<(&Unit, i32)> = vec![];
+        for (u, e) in factors {
+            match merged.iter_mut().find(|(m, _)| m.name == u.name) {
+                Some((_, me)) => *me = me.checked_add(*e)?,
+                None => merged.push((u, *e)),
+            }
+        }
+        merged.retain(|(_, e)| *e != 0);
+        let mut dimension = Dimension::NONE;
+        for (u, e) in &merged {
+            dimension = dimension.checked_mul(u.dimension.pow(f64::from(*e))?)?;
+        }
+        Some(Unit {
+            name: merged
+                .iter()
+                .map(|(u, e)| factor(&u.name, *e))
+                .collect::<Vec<_>>()
+                .join(" "),
+            factor: merged.iter().map(|(u, e)| u.factor.powi(*e)).product(),
+            dimension,
+        })
+    }
+
+    /// the name, as displayed
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// the dimension
+    pub fn dimension(&self) -> Dimension {
+        self.dimension
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// A value with a dimension, stored in SI base units and displayed in its unit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    value: f64,
+    unit: Unit,
+}
+
+impl Quantity {
+    /// new quantity from a value in the given unit
+    pub fn new(value: f64, unit: Unit) -> Quantity {
+        Quantity {
+            value: value * unit.factor,
+            unit,
+        }
+    }
+
+    /// new quantity from a value in SI base units, displayed in the given unit
+    pub fn from_si(value: f64, unit: Unit) -> Quantity {
+        Quantity { value, unit }
+    }
+
+    /// the value in SI base units
+    pub fn si_value(&self) -> f64 {
+        self.value
+    }
+
+    /// the value in the unit of the quantity
+    pub fn value(&self) -> f64 {
+        self.value / self.unit.factor
+    }
+
+    /// the unit the quantity is displayed in
+    pub fn unit(&self) -> &Unit {
+        &self.unit
+    }
+
+    /// the dimension
+    pub fn dimension(&self) -> Dimension {
+        self.unit.dimension
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.value(), self.unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dimension() {
+        let length = Unit::named("m").unwrap().dimension();
+        let time = Unit::named("s").unwrap().dimension();
+        let area = length.checked_mul(length).unwrap();
+        assert_eq!("m s^-1", length.checked_div(time).unwrap().to_string());
+        assert_eq!(Some(Dimension::NONE), length.checked_div(length));
+        let kg = Unit::named("kg").unwrap().dimension();
+        assert_eq!(
+            Some(Unit::named("N").unwrap().dimension()),
+            kg.checked_mul(length)
+                .and_then(|d| d.checked_div(time))
+                .and_then(|d| d.checked_div(time))
+        );
+        let huge = length.pow(2e9).unwrap();
+        assert_eq!(None, huge.checked_mul(huge));
+        assert_eq!(None, huge.checked_div(huge.pow(-1.0).unwrap()));
+        assert_eq!(
+            "m^2 kg s^-2",
+            Unit::named("J").unwrap().dimension().to_string()
+        );
+        assert_eq!(Some(length), area.pow(0.5));
+        assert_eq!(None, length.pow(0.5));
+        assert!(Dimension::NONE.is_none());
+    }
+
+    #[test]
+    fn test_units() {
+        let u = |name| Unit::named(name).unwrap();
+        let speed = Unit::compound(&[(u("km"), 1), (u("h"), -1)]).unwrap();
+        assert_eq!("km h^-1", speed.name());
+        assert_eq!(1000.0 / 3600.0, speed.factor);
+        let area = Unit::compound(&[(u("m"), 1), (u("s"), 1), (u("m"), 1), (u("s"), -1)]);
+        assert_eq!("m^2", area.unwrap().name());
+        assert!(Unit::compound(&[(u("m"), i32::MAX), (u("m"), 1)]).is_none());
+        assert!(Unit::compound(&[(u("m"), i32::MAX), (u("km"), 1)]).is_none());
+        assert_eq!(None, Unit::named("furlong"));
+        assert_eq!("m s^-1", Unit::base(speed.dimension()).name());
+
+        let q = Quantity::new(36.0, speed);
+        assert_eq!(10.0, q.si_value());
+        assert_eq!(36.0, q.value());
+        assert_eq!("36 km h^-1", q.to_string());
+        assert_eq!("5 mi", Quantity::from_si(8046.72, u("mi")).to_string());
+    }
+}
@@ -0,0 +1,295 @@
+//! Values produced by evaluating expressions: numbers, booleans or quantities
+
+use super::{Dimension, EvalError, Number, Operator, Quantity, UnaryOperator, Unit};
+use std::fmt;
+
+/// The type of a value, reported in type errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Number,
+    Bool,
+    Quantity,
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueType::Number => write!(f, "a number"),
+            ValueType::Bool => write!(f, "a boolean"),
+            ValueType::Quantity => write!(f, "a quantity"),
+        }
+    }
+}
+
+/// The result of an evaluation, using the given number type.
+/// Quantities are always computed with floats, like functions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<N = f64> {
+    Number(N),
+    Bool(bool),
+    /// a number with a unit, never dimensionless
+    Quantity(Quantity),
+}
+
+impl<N> From<bool> for Value<N> {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Number(v)
+    }
+}
+
+impl<N> From<Quantity> for Value<N> {
+    fn from(q: Quantity) -> Self {
+        Value::Quantity(q)
+    }
+}
+
+impl<N: fmt::Display> fmt::Display for Value<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => n.fmt(f),
+            Value::Bool(b) => b.fmt(f),
+            Value::Quantity(q) => q.fmt(f),
+        }
+    }
+}
+
+impl Value {
+    /// convert a float value to the given number type
+    pub fn convert<N: Number>(self) -> Result<Value<N>, EvalError> {
+        match self {
+            Value::Number(v) => Ok(Value::Number(N::from_f64(v)?)),
+            Value::Bool(b) => Ok(Value::Bool(b)),
+            Value::Quantity(q) => Ok(Value::Quantity(q)),
+        }
+    }
+}
+
+impl<N: Number> Value<N> {
+    /// the type of the value
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Number(_) => ValueType::Number,
+            Value::Bool(_) => ValueType::Bool,
+            Value::Quantity(_) => ValueType::Quantity,
+        }
+    }
+
+    /// the number, or a type error
+    pub fn number(self) -> Result<N, EvalError> {
+        match self {
+            Value::Number(n) => Ok(n),
+            v => Err(EvalError::TypeMismatch {
+                expected: ValueType::Number,
+                found: v.value_type(),
+            }),
+        }
+    }
+
+    /// the boolean, or a type error
+    pub fn boolean(self) -> Result<bool, EvalError> {
+        match self {
+            Value::Bool(b) => Ok(b),
+            v => Err(EvalError::TypeMismatch {
+                expected: ValueType::Bool,
+                found: v.value_type(),
+            }),
+        }
+    }
+
+    /// the value as a quantity, plain numbers being dimensionless
+    fn quantity(self) -> Result<Quantity, EvalError> {
+        match self {
+            Value::Quantity(q) => Ok(q),
+            Value::Number(n) => Ok(Quantity::new(n.to_f64(), Unit::base(Dimension::NONE))),
+            Value::Bool(_) => Err(EvalError::TypeMismatch {
+                expected: ValueType::Number,
+                found: ValueType::Bool,
+            }),
+        }
+    }
+
+    /// wrap a quantity, turning dimensionless ones into plain numbers
+    pub fn from_quantity(q: Quantity) -> Result<Self, EvalError> {
+        if q.dimension().is_none() {
+            Ok(Value::Number(N::from_f64(q.si_value())?))
+        } else {
+            Ok(Value::Quantity(q))
+        }
+    }
+
+    /// convert a quantity to the given unit
+    pub fn to_unit(self, unit: &Unit) -> Result<Self, EvalError> {
+        let q = self.quantity()?;
+        if q.dimension() == unit.dimension() {
+            Value::from_quantity(Quantity::from_si(q.si_value(), unit.clone()))
+        } else {
+            Err(EvalError::IncompatibleUnits(
+                q.dimension(),
+                unit.dimension(),
+            ))
+        }
+    }
+
+    /// equality between two values of the same type
+    fn equals(self, other: Self) -> Result<bool, EvalError> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Ok(a == b),
+            (Value::Bool(a), Value::Bool(b)) => Ok(a == b),
+            (a, b) => Err(EvalError::TypeMismatch {
+                expected: a.value_type(),
+                found: b.value_type(),
+            }),
+        }
+    }
+
+    /// apply a unary operator
+    pub fn unary(op: UnaryOperator, a: Self) -> Result<Self, EvalError> {
+        match (op, a) {
+            (UnaryOperator::Not, a) => Ok(Value::Bool(!a.boolean()?)),
+            (UnaryOperator::Negate, Value::Quantity(q)) => Ok(Value::Quantity(Quantity::from_si(
+                -q.si_value(),
+                q.unit().clone(),
+            ))),
+            (_, a) => Ok(Value::Number(N::unary(op, &a.number()?)?)),
+        }
+    }
+
+    /// apply a binary operator, evaluating both sides of `&&` and `||`
+    pub fn binary(op: Operator, a: Self, b: Self) -> Result<Self, EvalError> {
+        let quantities = matches!(a, Value::Quantity(_)) || matches!(b, Value::Quantity(_));
+        if quantities && (op.is_comparison() || (op.is_arithmetic() && !op.is_bitwise())) {
+            return quantity_binary(op, a, b);
+        }
+        Ok(match op {
+            Operator::Equal => Value::Bool(a.equals(b)?),
+            Operator::NotEqual => Value::Bool(!a.equals(b)?),
+            Operator::Less => Value::Bool(a.number()? < b.number()?),
+            Operator::LessEqual => Value::Bool(a.number()? <= b.number()?),
+            Operator::Greater => Value::Bool(a.number()? > b.number()?),
+            Operator::GreaterEqual => Value::Bool(a.number()? >= b.number()?),
+            Operator::And => Value::Bool(a.boolean()? & b.boolean()?),
+            Operator::Or => Value::Bool(a.boolean()? | b.boolean()?),
+            _ => Value::Number(N::binary(op, &a.number()?, &b.number()?)?),
+        })
+    }
+}
+
+/// apply an arithmetic or comparison operator when at least one side is a quantity
+fn quantity_binary<N: Number>(
+    op: Operator,
+    a: Value<N>,
+    b: Value<N>,
+) -> Result<Value<N>, EvalError> {
+    if op == Operator::Power {
+        let (q, p) = (a.quantity()?, b.number()?.to_f64());
+        let dimension = q
+            .dimension()
+            .pow(p)
+            .ok_or_else(|| EvalError::NotRepresentable(format!("({})^{}", q.unit(), p)))?;
+        return Value::from_quantity(Quantity::from_si(
+            q.si_value().powf(p),
+            Unit::base(dimension),
+        ));
+    }
+    let (a, b) = (a.quantity()?, b.quantity()?);
+    let (va, vb) = (a.si_value(), b.si_value());
+    match op {
+        Operator::Multiply | Operator::Divide => {
+            let (v, dimension) = if op == Operator::Multiply {
+                (va * vb, a.dimension().checked_mul(b.dimension()))
+            } else {
+                (va / vb, a.dimension().checked_div(b.dimension()))
+            };
+            let dimension = dimension.ok_or_else(|| {
+                EvalError::NotRepresentable(format!(
+                    "({}) {} ({})",
+                    a.unit(),
+                    op.symbol(),
+                    b.unit()
+                ))
+            })?;
+            // scaling by a plain number keeps the unit
+            let unit = if b.dimension().is_none() {
+                a.unit().clone()
+            } else if a.dimension().is_none() && op == Operator::Multiply {
+                b.unit().clone()
+            } else {
+                Unit::base(dimension)
+            };
+            Value::from_quantity(Quantity::from_si(v, unit))
+        }
+        _ if a.dimension() != b.dimension() => {
+            Err(EvalError::IncompatibleUnits(a.dimension(), b.dimension()))
+        }
+        Operator::Add | Operator::Substract | Operator::Modulo => {
+            let unit = if a.unit() == b.unit() {
+                a.unit().clone()
+            } else {
+                Unit::base(a.dimension())
+            };
+            Value::from_quantity(Quantity::from_si(op.apply(va, vb), unit))
+        }
+        Operator::Equal => Ok(Value::Bool(va == vb)),
+        Operator::NotEqual => Ok(Value::Bool(va != vb)),
+        Operator::Less => Ok(Value::Bool(va < vb)),
+        Operator::LessEqual => Ok(Value::Bool(va <= vb)),
+        Operator::Greater => Ok(Value::Bool(va > vb)),
+        Operator::GreaterEqual => Ok(Value::Bool(va >= vb)),
+        _ => unreachable!("'{}' does not apply to quantities", op.symbol()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value() {
+        let (one, two, t): (Value, Value, Value) = (1.0.into(), 2.0.into(), true.into());
+        let binary = |op, a: &Value, b: &Value| Value::binary(op, a.clone(), b.clone());
+        assert_eq!(Ok(Value::Bool(true)), binary(Operator::Less, &one, &two));
+        assert_eq!(
+            Ok(Value::Bool(false)),
+            binary(Operator::GreaterEqual, &one, &two)
+        );
+        assert_eq!(
+            Ok(Value::Bool(true)),
+            binary(Operator::NotEqual, &one, &two)
+        );
+        assert_eq!(Ok(Value::Bool(true)), binary(Operator::Equal, &t, &t));
+        assert_eq!(Ok(Value::Number(3.0)), binary(Operator::Add, &one, &two));
+        assert_eq!(
+            Ok(Value::Bool(false)),
+            Value::unary(UnaryOperator::Not, t.clone())
+        );
+        assert_eq!(
+            Err(EvalError::TypeMismatch {
+                expected: ValueType::Number,
+                found: ValueType::Bool
+            }),
+            binary(Operator::Add, &one, &t)
+        );
+        assert_eq!(
+            Err(EvalError::TypeMismatch {
+                expected: ValueType::Bool,
+                found: ValueType::Number
+            }),
+            binary(Operator::Equal, &t, &one)
+        );
+        assert_eq!(
+            Err(EvalError::TypeMismatch {
+                expected: ValueType::Bool,
+                found: ValueType::Number
+            }),
+            Value::unary(UnaryOperator::Not, one.clone())
+        );
+        assert_eq!("true", t.to_string());
+        assert_eq!("1.5", Value::Number(1.5).to_string());
+    }
+}
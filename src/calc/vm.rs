@@ -0,0 +1,376 @@
+//! Compile calculator nodes to a flat instruction vector, executed by a stack machine without recursion
+
+use super::{
+    Environment, EvalError, Function, Node, Operator, Quantity, UnaryOperator, Unit, Value,
+};
+
+/// An instruction of the stack machine
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    /// push a constant
+    Push(Value),
+    /// push the value of the variable in the given slot
+    Load(usize),
+    /// replace the value on top of the stack by the result of the operator
+    Unary(UnaryOperator),
+    /// replace the two values on top of the stack by the result of the operator
+    Binary(Operator),
+    /// replace the given number of values on top of the stack by the result of the function at the given index
+    Call(usize, usize),
+    /// continue at the given instruction index
+    Jump(usize),
+    /// pop a boolean, continuing at the given instruction index if it is false
+    JumpIfFalse(usize),
+    /// convert the quantity on top of the stack to the unit
+    Convert(Unit),
+}
+
+/// A compiled node
+#[derive(Debug, Clone)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+    /// variable names, in slot order
+    variables: Vec<String>,
+    /// functions resolved at compilation time
+    functions: Vec<(String, Function)>,
+    /// maximum size the stack can reach
+    stack_size: usize,
+}
+
+impl Program {
+    /// the instructions
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// the variable names, in the order expected by `run_slots`
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// run the program, looking up variables in the given environment
+    pub fn run(&self, env: &Environment) -> Result<Value, EvalError> {
+        let values = self
+            .variables
+            .iter()
+            .map(|name| {
+                env.get(name)
+                    .ok_or_else(|| EvalError::UnboundVariable(name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.run_slots(&values)
+    }
+
+    /// run the program with the variable values given in slot order
+    /// panics if the number of values is not the number of variables
+    pub fn run_slots(&self, values: &[Value]) -> Result<Value, EvalError> {
+        assert_eq!(
+            self.variables.len(),
+            values.len(),
+            "wrong number of variable values"
+        );
+        let mut stack = Vec::with_capacity(self.stack_size);
+        let mut args = vec![];
+        let mut pc = 0;
+        while let Some(i) = self.instructions.get(pc) {
+            pc += 1;
+            match *i {
+                Instruction::Push(ref v) => stack.push(v.clone()),
+                Instruction::Load(slot) => stack.push(values[slot].clone()),
+                Instruction::Unary(op) => {
+                    let a = stack.pop().unwrap();
+                    stack.push(Value::unary(op, a)?);
+                }
+                Instruction::Binary(op) => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(Value::binary(op, a, b)?);
+                }
+                Instruction::Call(ix, count) => {
+                    let start = stack.len() - count;
+                    args.clear();
+                    for v in stack.drain(start..) {
+                        args.push(v.number()?);
+                    }
+                    stack.push(Value::Number((self.functions[ix].1.body)(&args)));
+                }
+                Instruction::Jump(target) => pc = target,
+                Instruction::JumpIfFalse(target) => {
+                    if !stack.pop().unwrap().boolean()? {
+                        pc = target;
+                    }
+                }
+                Instruction::Convert(ref unit) => {
+                    let a = stack.pop().unwrap();
+                    stack.push(a.to_unit(unit)?);
+                }
+            }
+        }
+        Ok(stack.pop().unwrap())
+    }
+}
+
+/// compile a node, resolving the functions it calls in the given environment.
+/// User defined functions cannot be compiled, calling them is an `UnknownFunction` error
+pub fn compile(t: &Node, env: &Environment) -> Result<Program, EvalError> {
+    let mut compiler = Compiler {
+        env,
+        program: Program {
+            instructions: vec![],
+            variables: vec![],
+            functions: vec![],
+            stack_size: 0,
+        },
+        depth: 0,
+    };
+    compiler.compile(t)?;
+    Ok(compiler.program)
+}
+
+/// Compilation state
+struct Compiler<'a> {
+    env: &'a Environment,
+    program: Program,
+    /// current stack depth
+    depth: usize,
+}
+
+impl<'a> Compiler<'a> {
+    /// compile a node, emitting the instructions that leave its value on top of the stack
+    fn compile(&mut self, t: &Node) -> Result<(), EvalError> {
+        match t {
+            Node::Value(v) | Node::Literal(v, _) => {
+                self.emit(Instruction::Push(Value::Number(*v)), 1, 0)
+            }
+            Node::Variable(name) => {
+                let variables = &mut self.program.variables;
+                let slot = match variables.iter().position(|n| n == name) {
+                    Some(slot) => slot,
+                    None => {
+                        variables.push(name.clone());
+                        variables.len() - 1
+                    }
+                };
+                self.emit(Instruction::Load(slot), 1, 0);
+            }
+            Node::SubNode(n) => self.compile(n)?,
+            Node::Unary(op, n) => {
+                self.compile(n)?;
+                self.emit(Instruction::Unary(*op), 1, 1);
+            }
+            // a && b: if a then (if b then true else false) else false
+            Node::Binary(Operator::And, n1, n2) => {
+                self.compile_if(
+                    n1,
+                    |c| c.compile_if(n2, |c| c.push(true), |c| c.push(false)),
+                    |c| c.push(false),
+                )?;
+            }
+            // a || b: if a then true else (if b then true else false)
+            Node::Binary(Operator::Or, n1, n2) => {
+                self.compile_if(
+                    n1,
+                    |c| c.push(true),
+                    |c| c.compile_if(n2, |c| c.push(true), |c| c.push(false)),
+                )?;
+            }
+            Node::Quantity(v, unit) => {
+                let q = Value::from_quantity(Quantity::new(*v, unit.clone()))?;
+                self.emit(Instruction::Push(q), 1, 0);
+            }
+            Node::Convert(n, unit) => {
+                self.compile(n)?;
+                self.emit(Instruction::Convert(unit.clone()), 1, 1);
+            }
+            Node::If(cond, n1, n2) => {
+                self.compile_if(cond, |c| c.compile(n1), |c| c.compile(n2))?;
+            }
+            Node::Binary(op, n1, n2) => {
+                self.compile(n1)?;
+                self.compile(n2)?;
+                self.emit(Instruction::Binary(*op), 1, 2);
+            }
+            Node::Call(name, args) => {
+                let f = self
+                    .env
+                    .function(name)
+                    .ok_or_else(|| EvalError::UnknownFunction(name.clone()))?;
+                f.check_arity(name, args.len())?;
+                for a in args {
+                    self.compile(a)?;
+                }
+                let functions = &mut self.program.functions;
+                let ix = match functions.iter().position(|(n, _)| n == name) {
+                    Some(ix) => ix,
+                    None => {
+                        functions.push((name.clone(), f.clone()));
+                        functions.len() - 1
+                    }
+                };
+                self.emit(Instruction::Call(ix, args.len()), 1, args.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// compile a conditional, the branches being compiled by the given closures
+    fn compile_if<F, G>(&mut self, cond: &Node, then: F, otherwise: G) -> Result<(), EvalError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), EvalError>,
+        G: FnOnce(&mut Self) -> Result<(), EvalError>,
+    {
+        self.compile(cond)?;
+        let to_otherwise = self.program.instructions.len();
+        self.emit(Instruction::JumpIfFalse(0), 0, 1);
+        // only one branch runs, both start from the same depth
+        let depth = self.depth;
+        then(self)?;
+        let to_end = self.program.instructions.len();
+        self.emit(Instruction::Jump(0), 0, 0);
+        self.patch(to_otherwise);
+        self.depth = depth;
+        otherwise(self)?;
+        self.patch(to_end);
+        Ok(())
+    }
+
+    /// push a boolean constant
+    fn push(&mut self, b: bool) -> Result<(), EvalError> {
+        self.emit(Instruction::Push(Value::Bool(b)), 1, 0);
+        Ok(())
+    }
+
+    /// make the jump at the given index target the next instruction
+    fn patch(&mut self, ix: usize) {
+        let next = self.program.instructions.len();
+        match &mut self.program.instructions[ix] {
+            Instruction::Jump(target) | Instruction::JumpIfFalse(target) => *target = next,
+            i => unreachable!("{:?} is not a jump", i),
+        }
+    }
+
+    /// emit an instruction that pops then pushes the given number of values
+    fn emit(&mut self, i: Instruction, pushed: usize, popped: usize) {
+        self.program.instructions.push(i);
+        self.depth = self.depth - popped + pushed;
+        self.program.stack_size = self.program.stack_size.max(self.depth);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{eval_with, parse, ValueType};
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let mut env = Environment::new();
+        env.set("x", 2.0);
+        let p = compile(&parse("1 + x * -x").unwrap(), &env).unwrap();
+        assert_eq!(
+            &[
+                Instruction::Push(Value::Number(1.0)),
+                Instruction::Load(0),
+                Instruction::Load(0),
+                Instruction::Unary(UnaryOperator::Negate),
+                Instruction::Binary(Operator::Multiply),
+                Instruction::Binary(Operator::Add),
+            ],
+            p.instructions()
+        );
+        assert_eq!(&[String::from("x")], p.variables());
+        assert_eq!(3, p.stack_size);
+        assert_eq!(Ok(Value::Number(-3.0)), p.run(&env));
+        assert_eq!(Ok(Value::Number(-8.0)), p.run_slots(&[Value::Number(3.0)]));
+    }
+
+    #[test]
+    fn test_compile_and() {
+        let env = Environment::new();
+        let p = compile(&parse("x && y").unwrap(), &env).unwrap();
+        assert_eq!(
+            &[
+                Instruction::Load(0),
+                Instruction::JumpIfFalse(8),
+                Instruction::Load(1),
+                Instruction::JumpIfFalse(6),
+                Instruction::Push(Value::Bool(true)),
+                Instruction::Jump(7),
+                Instruction::Push(Value::Bool(false)),
+                Instruction::Jump(9),
+                Instruction::Push(Value::Bool(false)),
+            ],
+            p.instructions()
+        );
+        assert_eq!(1, p.stack_size);
+        // the right side is not evaluated when the left side is false
+        assert_eq!(
+            Ok(Value::Bool(false)),
+            p.run_slots(&[false.into(), 1.0.into()])
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        let mut env = Environment::new();
+        env.set("x", 1.25);
+        env.set("rate", 12.0);
+        env.define_function("hypot", 2, |a| a[0].hypot(a[1]));
+        for txt in [
+            "2 * (3 + (4 * 5 + (6 * 7) * 8) - 9) * 10",
+            "2*-3--4+-.25",
+            "2^3^2 % 7",
+            "rate * x - x / rate",
+            "max(x, rate, 3) + min(1, x)",
+            "sqrt(2) * pi + sin(x)^2 + cos(x)^2",
+            "hypot(3 * x, max(1, 2, hypot(x, rate)))",
+            "-(x + 1) ^ -x",
+            "x < 2 && rate >= 12 || !(x == 1)",
+            "x > 2 || rate / x != 0 && true",
+            "if(x <= 1, 1, x * if(rate < 0, 0, rate))",
+            "if(!(x > 1) || x, 1, 2)",
+            "if(1, 2, 3)",
+            "false && 1",
+            "(x < 2) + 1",
+        ]
+        .iter()
+        {
+            let n = parse(txt).unwrap();
+            let p = compile(&n, &env).unwrap();
+            assert_eq!(eval_with(&n, &env), p.run(&env), "{}", txt);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let env = Environment::new();
+        assert_eq!(
+            EvalError::UnknownFunction(String::from("f")),
+            compile(&parse("1 + f(2)").unwrap(), &env).unwrap_err()
+        );
+        assert_eq!(
+            EvalError::WrongArity {
+                name: String::from("sin"),
+                found: 2
+            },
+            compile(&parse("sin(1, 2)").unwrap(), &env).unwrap_err()
+        );
+        let p = compile(&parse("if(x, 1 < y, 2)").unwrap(), &env).unwrap();
+        assert_eq!(
+            Err(EvalError::TypeMismatch {
+                expected: ValueType::Bool,
+                found: ValueType::Number
+            }),
+            p.run_slots(&[Value::Number(1.0), Value::Number(3.0)])
+        );
+        assert_eq!(
+            Ok(Value::Bool(true)),
+            p.run_slots(&[true.into(), 3.0.into()])
+        );
+        let p = compile(&parse("x + y").unwrap(), &env).unwrap();
+        assert_eq!(
+            Err(EvalError::UnboundVariable(String::from("x"))),
+            p.run(&env)
+        );
+    }
+}
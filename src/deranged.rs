@@ -6,7 +6,6 @@ use std::fs::File;
 use std::io;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::usize::MAX;
 
 /// Get words from unix dictionary file
 pub fn get_words() -> Result<Vec<String>, io::Error> {
@@ -34,7 +33,7 @@ pub fn longest_deranged(v: &mut Vec<String>) -> Option<(String, String)> {
     // keep all strings keyed by sorted characters (since anagrams have the same list of sorted characters)
     let mut signatures: HashMap<Vec<char>, Vec<&String>> = HashMap::new();
     // save on memory by only keeping in the map strings of the current processed length
-    let mut previous_length = MAX;
+    let mut previous_length = usize::MAX;
     for s in v {
         // length change, clear the map
         if s.len() < previous_length {
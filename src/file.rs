@@ -86,6 +86,7 @@ pub fn overwrite_password_file(file_name: &str, recs: &Vec<PasswordRecord>) -> R
     let f = OpenOptions::new()
         .create(true)
         .write(true)
+        .truncate(true)
         .open(file_name)?;
     write_records(f, recs)
 }
@@ -183,4 +184,16 @@ mod tests {
         assert_eq!(full, recs4);
         fs::remove_file("passwd").expect("cannot delete file");
     }
+
+    #[test]
+    fn test_overwrite_shorter() {
+        let file = "passwd_shorter";
+        let rec = |name| PasswordRecord::new(name, "x", 1001, 1000, vec![name], "/home", "/bin/sh");
+        overwrite_password_file(file, &vec![rec("longer_name"), rec("other")])
+            .expect("cannot write file");
+        overwrite_password_file(file, &vec![rec("short")]).expect("cannot write file");
+        let recs = read_password_file(file).expect("cannot read file");
+        fs::remove_file(file).expect("cannot delete file");
+        assert_eq!(vec![rec("short")], recs);
+    }
 }
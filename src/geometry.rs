@@ -6,7 +6,7 @@ pub fn check_straight_line(coordinates: Vec<Vec<i32>>) -> bool {
     if coordinates.len()<3{
         return true;
     }
-    if let Some(p1) = coordinates.get(0){
+    if let Some(p1) = coordinates.first(){
         if let Some(p2) = coordinates.get(1){
             let slope1 = (p2[1] - p1[1]) as f32 /  (p2[0] - p1[0]) as f32;
             for c in &coordinates[2..] {
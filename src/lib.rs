@@ -21,5 +21,6 @@ pub use crate::file::*;
 pub use crate::geometry::*;
 pub use crate::llist::*;
 pub use crate::math::*;
-pub use crate::matrix::*;
+// not `Solution`, that would clash with `btree::Solution`
+pub use crate::matrix::{count_negatives, equal_pairs};
 pub use crate::string::*;